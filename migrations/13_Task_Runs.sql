@@ -0,0 +1,8 @@
+CREATE TABLE task_runs (
+    name        VARCHAR(64) NOT NULL PRIMARY KEY,
+    running     BOOLEAN NOT NULL DEFAULT false,
+    started     TIMESTAMP NOT NULL DEFAULT LOCALTIMESTAMP,
+    finished    TIMESTAMP,
+    duration_ms BIGINT,
+    error       TEXT
+);
@@ -0,0 +1,5 @@
+-- Every duration used to be stored as zero, the intended value is lost, so fall back to keeping messages
+UPDATE channels SET persistence = 0, persistence_duration_seconds = NULL
+    WHERE persistence = 1 AND persistence_duration_seconds <= 0;
+UPDATE channels SET persistence = 2, persistence_duration_seconds = NULL
+    WHERE persistence = 3 AND persistence_duration_seconds <= 0;
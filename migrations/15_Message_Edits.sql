@@ -0,0 +1 @@
+ALTER TABLE messages ADD edited_at TIMESTAMP;
@@ -0,0 +1,2 @@
+-- Not a foreign key, replies keep referring to messages that have been deleted
+ALTER TABLE messages ADD reply_to BIGINT;
@@ -0,0 +1,11 @@
+CREATE TABLE message_reactions (
+    message     BIGINT NOT NULL,
+    player      UUID NOT NULL,
+    emoji       VARCHAR(64) NOT NULL,
+    created     TIMESTAMP NOT NULL DEFAULT LOCALTIMESTAMP,
+
+    PRIMARY KEY (message, player, emoji),
+
+    FOREIGN KEY (message) REFERENCES messages(id) ON DELETE CASCADE,
+    FOREIGN KEY (player) REFERENCES players(uuid) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+CREATE TABLE channel_read_markers (
+    channel_id  BIGINT NOT NULL,
+    player      UUID NOT NULL,
+    -- The latest message read, it may have been deleted since
+    message     BIGINT NOT NULL,
+
+    PRIMARY KEY (channel_id, player),
+
+    FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+    FOREIGN KEY (player) REFERENCES players(uuid) ON DELETE CASCADE
+);
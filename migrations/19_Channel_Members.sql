@@ -0,0 +1,33 @@
+CREATE TYPE channel_role AS ENUM (
+    'owner', -- The owner is stored in channels.owner, this role should never appear in channel_members
+    'moderator',
+    'member',
+    'read_only'
+);
+
+-- Replaces channel_memberships, the owner is stored in channels.owner only. The join time is used to pass ownership on
+-- to the longest-standing participant.
+CREATE TABLE channel_members (
+    channel_id  BIGINT NOT NULL,
+    player      UUID NOT NULL,
+    joined_at   TIMESTAMP NOT NULL DEFAULT LOCALTIMESTAMP,
+    role        CHANNEL_ROLE NOT NULL DEFAULT 'member' CHECK (role != 'owner'),
+
+    PRIMARY KEY (channel_id, player),
+
+    FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+    FOREIGN KEY (player) REFERENCES players(uuid) ON DELETE CASCADE
+);
+
+CREATE INDEX channel_members_player ON channel_members (player);
+
+-- Ids of deleted channels may have been left in the arrays, and players may appear more than once. The actual join
+-- times of existing participants are unknown, the creation of the channel is the earliest they could have joined.
+INSERT INTO channel_members (channel_id, player, joined_at)
+    SELECT DISTINCT channels.id, channel_memberships.player, channels.created
+    FROM channel_memberships
+    JOIN channels ON channels.id = ANY(channel_memberships.channels)
+    WHERE channel_memberships.player != channels.owner
+    ON CONFLICT DO NOTHING;
+
+DROP TABLE channel_memberships;
@@ -0,0 +1,14 @@
+CREATE TABLE channel_invite_codes (
+    code        VARCHAR(16) NOT NULL PRIMARY KEY,
+    channel_id  BIGINT NOT NULL,
+    creator     UUID NOT NULL,
+    created     TIMESTAMP NOT NULL DEFAULT LOCALTIMESTAMP,
+    expires     TIMESTAMP,
+    max_uses    INTEGER,
+    uses        INTEGER NOT NULL DEFAULT 0,
+
+    FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+    FOREIGN KEY (creator) REFERENCES players(uuid) ON DELETE CASCADE
+);
+
+CREATE INDEX channel_invite_codes_channel ON channel_invite_codes (channel_id);
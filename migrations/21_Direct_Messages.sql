@@ -0,0 +1,14 @@
+-- Channels between two friends. player_a is the player with the lower uuid, they are stored as the owner of the
+-- channel and player_b as its only member, but neither has any privileges.
+CREATE TABLE direct_messages (
+    channel_id  BIGINT NOT NULL PRIMARY KEY,
+    player_a    UUID NOT NULL,
+    player_b    UUID NOT NULL,
+
+    UNIQUE (player_a, player_b),
+    CHECK (player_a < player_b),
+
+    FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+    FOREIGN KEY (player_a) REFERENCES players(uuid) ON DELETE CASCADE,
+    FOREIGN KEY (player_b) REFERENCES players(uuid) ON DELETE CASCADE
+);
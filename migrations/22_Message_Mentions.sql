@@ -0,0 +1,12 @@
+-- Players mentioned by a message, either by `@username` or through `@everyone`, excluding the sender
+ALTER TABLE messages ADD COLUMN mentions UUID[] NOT NULL DEFAULT '{}';
+
+UPDATE messages SET mentions = ARRAY(
+    SELECT players.uuid FROM players
+    WHERE players.uuid != messages.sender
+        AND (
+            players.uuid = (SELECT owner FROM channels WHERE channels.id = messages.channel_id)
+            OR players.uuid IN (SELECT player FROM channel_members WHERE channel_members.channel_id = messages.channel_id)
+        )
+        AND messages.content ~* ('(^|[^[:alnum:]_])@(' || players.username || '|everyone)([^[:alnum:]_]|$)')
+);
@@ -0,0 +1,13 @@
+-- Pinned messages are exempt from the persistence of their channel
+CREATE TABLE message_pins (
+    message     BIGINT NOT NULL PRIMARY KEY,
+    channel_id  BIGINT NOT NULL,
+    pinned_by   UUID,
+    pinned      TIMESTAMP NOT NULL DEFAULT LOCALTIMESTAMP,
+
+    FOREIGN KEY (message) REFERENCES messages(id) ON DELETE CASCADE,
+    FOREIGN KEY (channel_id) REFERENCES channels(id) ON DELETE CASCADE,
+    FOREIGN KEY (pinned_by) REFERENCES players(uuid) ON DELETE SET NULL
+);
+
+CREATE INDEX message_pins_channel_id ON message_pins (channel_id);
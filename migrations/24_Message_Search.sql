@@ -0,0 +1,2 @@
+-- Full-text search over messages, the 'simple' configuration is used since messages are in many languages
+CREATE INDEX messages_content_search ON messages USING GIN (to_tsvector('simple', content));
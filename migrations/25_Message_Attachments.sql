@@ -0,0 +1,13 @@
+-- Images attached to messages, they are not evicted while the message exists
+CREATE TABLE message_attachments (
+    message     BIGINT NOT NULL,
+    image       BIGINT NOT NULL,
+    position    SMALLINT NOT NULL,
+
+    PRIMARY KEY (message, image),
+
+    FOREIGN KEY (message) REFERENCES messages(id) ON DELETE CASCADE,
+    FOREIGN KEY (image) REFERENCES images(id) ON DELETE CASCADE
+);
+
+CREATE INDEX message_attachments_image ON message_attachments (image);
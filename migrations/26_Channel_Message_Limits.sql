@@ -0,0 +1,7 @@
+-- Limits on the messages of participants, the owner is exempt from them
+ALTER TABLE channels
+    ADD COLUMN slow_mode_seconds INTEGER NOT NULL DEFAULT 0 CHECK (slow_mode_seconds BETWEEN 0 AND 21600),
+    ADD COLUMN max_message_length SMALLINT NOT NULL DEFAULT 2000 CHECK (max_message_length BETWEEN 1 AND 2000);
+
+-- When the member last sent a message, for slow mode
+ALTER TABLE channel_members ADD COLUMN last_message TIMESTAMP;
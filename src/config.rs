@@ -0,0 +1,219 @@
+use crate::ClArgs;
+use anyhow::{Context, bail};
+use figment::{Figment, providers::Env, providers::Format, providers::Toml};
+use garde::Validate;
+use reqwest::Url;
+use serde::Deserialize;
+use sqlx::postgres::PgConnectOptions;
+use std::{fs::read_to_string, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+
+/// Configuration assembled from, in increasing order of precedence: defaults, the config file, environment variables
+/// prefixed with `AXOLOTL_CLIENT_API_` (nested keys are separated by `__`), and command line arguments.
+///
+/// See `docs/configuration.md` for an example.
+#[derive(Default, Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+#[garde(allow_unvalidated)]
+pub struct Config {
+	/// Postgres Connection Url, see: <https://docs.rs/sqlx/latest/sqlx/postgres/struct.PgConnectOptions.html>
+	pub postgres_url: Option<String>,
+	/// File containing a Postgres Connection Url
+	pub postgres_url_file: Option<PathBuf>,
+	pub hypixel_api_key: Option<String>,
+	/// File containing a Hypixel API Key, read on every request to the Hypixel API
+	pub hypixel_api_key_file: Option<PathBuf>,
+	/// File containing notes sent with `/global_data`, read on every full refresh
+	pub notes_file: Option<PathBuf>,
+	/// Base url under which the API is publicly reachable, used for links in image embeds
+	#[garde(inner(custom(valid_url)))]
+	pub domain_name: Option<String>,
+	#[garde(dive)]
+	pub server: Server,
+	#[garde(dive)]
+	pub timeouts: Timeouts,
+	#[garde(dive)]
+	pub limits: Limits,
+	#[garde(dive)]
+	pub retention: Retention,
+	#[garde(dive)]
+	pub upstream: Upstream,
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+#[garde(allow_unvalidated)]
+pub struct Server {
+	pub bind_address: SocketAddr,
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+	/// Requests to Mojang, Modrinth and Hypixel
+	#[garde(range(min = 1))]
+	pub upstream_seconds: u64,
+	/// Waiting for a connection from the database pool
+	#[garde(range(min = 1))]
+	pub database_acquire_seconds: u64,
+	/// Time without communication before a gateway connection is pinged, and then closed
+	#[garde(range(min = 1))]
+	pub gateway_keep_alive_seconds: u64,
+	/// Waiting for in-flight requests and gateway connections to finish after a shutdown signal
+	#[garde(skip)]
+	pub shutdown_seconds: u64,
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+	#[garde(range(min = 1))]
+	pub hypixel_cache_bytes: u64,
+	#[garde(range(min = 1))]
+	pub image_size_bytes: usize,
+	/// Participants per channel, not counting the owner
+	#[garde(range(min = 1))]
+	pub channel_participants: u32,
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+	/// Shared images are deleted this long after being uploaded, unless they are attached to a message
+	#[garde(range(min = 1))]
+	pub images_seconds: u64,
+}
+
+#[derive(Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct Upstream {
+	#[garde(custom(valid_url))]
+	pub mojang_session_server: String,
+	#[garde(custom(valid_url))]
+	pub modrinth_api: String,
+	#[garde(custom(valid_url))]
+	pub hypixel_api: String,
+}
+
+impl Default for Server {
+	fn default() -> Self {
+		Self {
+			bind_address: "[::]:8000".parse().unwrap(),
+		}
+	}
+}
+
+impl Default for Timeouts {
+	fn default() -> Self {
+		Self {
+			upstream_seconds: 10,
+			database_acquire_seconds: 30,
+			gateway_keep_alive_seconds: 10,
+			shutdown_seconds: 30,
+		}
+	}
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			hypixel_cache_bytes: 1024 * 1024 * 1024,
+			image_size_bytes: 1024 * 1024 * 8,
+			channel_participants: 100,
+		}
+	}
+}
+
+impl Default for Retention {
+	fn default() -> Self {
+		Self {
+			images_seconds: 7 * 24 * 60 * 60,
+		}
+	}
+}
+
+impl Default for Upstream {
+	fn default() -> Self {
+		Self {
+			mojang_session_server: "https://sessionserver.mojang.com/session/minecraft".to_string(),
+			modrinth_api: "https://api.modrinth.com/v2".to_string(),
+			hypixel_api: "https://api.hypixel.net/v2".to_string(),
+		}
+	}
+}
+
+fn valid_url(value: &str, _: &()) -> garde::Result {
+	Url::parse(value)
+		.map(|_| ())
+		.map_err(|error| garde::Error::new(error.to_string()))
+}
+
+impl Config {
+	pub fn load(cl_args: ClArgs) -> anyhow::Result<Config> {
+		let mut figment = Figment::new();
+		if let Some(file) = &cl_args.config {
+			if !file.is_file() {
+				bail!("config file {} does not exist", file.display());
+			}
+			figment = figment.merge(Toml::file_exact(file));
+		}
+		let mut config: Config = figment
+			.merge(Env::prefixed("AXOLOTL_CLIENT_API_").split("__"))
+			.extract()?;
+
+		// Options in a group replace each other, rather than conflicting
+		if cl_args.postgres.postgres_url.is_some() || cl_args.postgres.postgres_url_file.is_some() {
+			config.postgres_url = cl_args.postgres.postgres_url;
+			config.postgres_url_file = cl_args.postgres.postgres_url_file;
+		}
+		if cl_args.hypixel.hypixel_api_key.is_some() || cl_args.hypixel.hypixel_api_key_file.is_some() {
+			config.hypixel_api_key = cl_args.hypixel.hypixel_api_key;
+			config.hypixel_api_key_file = cl_args.hypixel.hypixel_api_key_file;
+		}
+		config.notes_file = cl_args.notes_file.or(config.notes_file);
+		config.domain_name = cl_args.domain_name.or(config.domain_name);
+		if let Some(cache_limit_bytes) = cl_args.cache_limit_bytes {
+			config.limits.hypixel_cache_bytes = cache_limit_bytes;
+		}
+		if let Some(bind_address) = cl_args.bind_address {
+			config.server.bind_address = bind_address;
+		}
+
+		config.validate()?;
+		if config.postgres_url.is_some() == config.postgres_url_file.is_some() {
+			bail!("exactly one of postgres_url or postgres_url_file must be set");
+		}
+		if config.hypixel_api_key.is_some() && config.hypixel_api_key_file.is_some() {
+			bail!("at most one of hypixel_api_key or hypixel_api_key_file may be set");
+		}
+
+		Ok(config)
+	}
+
+	pub fn postgres_connect_options(&self) -> anyhow::Result<PgConnectOptions> {
+		let postgres_url = match (&self.postgres_url, &self.postgres_url_file) {
+			(Some(postgres_url), _) => postgres_url.clone(),
+			(None, Some(file)) => read_to_string(file).with_context(|| format!("reading {}", file.display()))?,
+			(None, None) => unreachable!("Config::load should ensure that a url or url file is provided"),
+		};
+
+		PgConnectOptions::from_str(postgres_url.trim()).context("invalid postgres_url")
+	}
+}
+
+impl Timeouts {
+	pub fn upstream(&self) -> Duration {
+		Duration::from_secs(self.upstream_seconds)
+	}
+
+	pub fn database_acquire(&self) -> Duration {
+		Duration::from_secs(self.database_acquire_seconds)
+	}
+
+	pub fn gateway_keep_alive(&self) -> Duration {
+		Duration::from_secs(self.gateway_keep_alive_seconds)
+	}
+
+	pub fn shutdown(&self) -> Duration {
+		Duration::from_secs(self.shutdown_seconds)
+	}
+}
@@ -8,11 +8,12 @@ use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::{PgPool, query, query_as};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use super::{channel::Persistence, user::Activity};
+use super::{channel, channel::Persistence, user::Activity};
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct User {
 	uuid: Uuid,
 	username: String,
@@ -21,7 +22,7 @@ pub struct User {
 	previous_usernames: Vec<OldUsername>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct OldUsername {
 	username: String,
 	public: bool,
@@ -47,6 +48,7 @@ impl User {
 	}
 }
 
+#[utoipa::path(get, path = "/account", operation_id = "get_account", tag = "account", security(("access_token" = [])), responses((status = OK, body = User)))]
 pub async fn get(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -54,42 +56,324 @@ pub async fn get(
 	Ok(Json(User::get(&database, &uuid).await?))
 }
 
+/// Immediately and irrecoverably deletes the users account and associated data. Channels owned by the user are passed
+/// on to their longest-standing participant, or deleted if they have none.
+#[utoipa::path(delete, path = "/account", operation_id = "delete_account", tag = "account", security(("access_token" = [])), responses((status = NO_CONTENT)))]
 pub async fn delete(
-	State(ApiState { database, .. }): State<ApiState>,
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
 	Authentication(uuid): Authentication,
 ) -> Result<StatusCode, ApiError> {
+	let mut transaction = database.begin().await?;
+	let passed_on = channel::pass_on_channels(&mut transaction, &uuid).await?;
+	let memberships = query!(
+		r#"SELECT channel_members.channel_id, channels.owner FROM channel_members
+			JOIN channels ON channels.id = channel_members.channel_id
+			WHERE player = $1 AND channel_members.channel_id NOT IN (SELECT channel_id FROM direct_messages)"#,
+		uuid
+	)
+	.fetch_all(&mut *transaction)
+	.await?;
+	let dms = query!(
+		r#"DELETE FROM channels USING direct_messages
+			WHERE channels.id = direct_messages.channel_id AND $1 IN (player_a, player_b)
+			RETURNING channels.id, CASE WHEN player_a = $1 THEN player_b ELSE player_a END AS "other!""#,
+		uuid
+	)
+	.fetch_all(&mut *transaction)
+	.await?;
 	query!("DELETE FROM players WHERE uuid = $1", uuid)
-		.execute(&database)
+		.execute(&mut *transaction)
 		.await?;
+	transaction.commit().await?;
+
+	for (channel_id, owner) in passed_on {
+		channel::announce_owner_change(&database, &socket_sender, Id::from(*channel_id), &owner, &uuid).await;
+		channel::announce_leave(&database, &socket_sender, &channel_id, &owner, &uuid, None).await;
+	}
+	for membership in memberships {
+		let channel_id = Id::from(membership.channel_id as u64);
+		channel::announce_leave(&database, &socket_sender, &channel_id, &membership.owner, &uuid, None).await;
+	}
+	for dm in dms {
+		if let Some(socket) = socket_sender.get(&dm.other) {
+			let _ = socket.send(
+				serde_json::to_string(&json!({
+					"target": "channel_deleted",
+					"channel": dm.id
+				}))
+				.unwrap(),
+			);
+		}
+	}
 
 	Ok(StatusCode::NO_CONTENT)
 }
 
+/// Get a list of all channel ids the authenticated user participates in (owner + participant), optionally with their
+/// unread state, or summaries of the channels ordered by recent activity. Direct message channels are not included.
+#[utoipa::path(
+	get,
+	path = "/channels",
+	tag = "channel",
+	params(ChannelsQuery),
+	security(("access_token" = [])),
+	responses((status = OK, body = Channels))
+)]
 pub async fn get_channels(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
-) -> Result<Json<Vec<u64>>, ApiError> {
+	Query(ChannelsQuery { unread, summary }): Query<ChannelsQuery>,
+) -> Result<Json<Channels>, ApiError> {
+	if summary {
+		return Ok(Json(Channels::Summaries(get_channel_summaries(&database, &uuid).await?)));
+	}
+
 	let mut response = Vec::new();
 
-	let owned = query!("SELECT id FROM channels WHERE owner = $1", uuid)
-		.fetch_all(&database)
-		.await?;
-	let participating = query!("SELECT channels FROM channel_memberships WHERE player = $1", uuid)
-		.fetch_optional(&database)
-		.await?;
+	let owned =
+		query!("SELECT id FROM channels WHERE owner = $1 AND id NOT IN (SELECT channel_id FROM direct_messages)", uuid)
+			.fetch_all(&database)
+			.await?;
+	let participating = query!(
+		r#"SELECT channel_id FROM channel_members
+			WHERE player = $1 AND channel_id NOT IN (SELECT channel_id FROM direct_messages)
+			ORDER BY joined_at"#,
+		uuid
+	)
+	.fetch_all(&database)
+	.await?;
 
 	for id in owned {
 		response.push(id.id as u64);
 	}
-	if let Some(ids) = participating {
-		for id in ids.channels {
-			response.push(id as u64);
-		}
+	for id in participating {
+		response.push(id.channel_id as u64);
+	}
+
+	if !unread {
+		return Ok(Json(Channels::Ids(response)));
 	}
 
-	Ok(Json(response))
+	let ids: Vec<i64> = response.iter().map(|id| *id as i64).collect();
+	let counts = query!(
+		r#"SELECT channels.id AS "id!",
+				COUNT(messages.id) AS "unread!",
+				COUNT(messages.id) FILTER (WHERE $2 = ANY(messages.mentions)) AS "mentions!"
+			FROM UNNEST($1::BIGINT[]) AS channels(id)
+			LEFT JOIN channel_read_markers AS markers ON markers.channel_id = channels.id AND markers.player = $2
+			LEFT JOIN messages ON messages.channel_id = channels.id
+				AND messages.id > COALESCE(markers.message, 0)
+				AND messages.sender != $2
+				-- Messages by blocked users are hidden, so they shouldn't count either
+				AND messages.sender NOT IN (SELECT player_b FROM relations WHERE player_a = $2 AND relation = 'blocked')
+			GROUP BY channels.id"#,
+		&ids,
+		uuid
+	)
+	.fetch_all(&database)
+	.await?;
+
+	Ok(Json(Channels::Unread(
+		response
+			.into_iter()
+			.map(|id| {
+				let counts = counts.iter().find(|counts| counts.id as u64 == id);
+				UnreadChannel {
+					id,
+					unread: counts.map_or(0, |counts| counts.unread as u32),
+					mentions: counts.map_or(0, |counts| counts.mentions as u32),
+				}
+			})
+			.collect(),
+	)))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChannelsQuery {
+	/// Whether to include unread and mention counts
+	#[serde(default)]
+	unread: bool,
+	/// Whether to return channel summaries, which include the unread state
+	#[serde(default)]
+	summary: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum Channels {
+	Ids(Vec<u64>),
+	Unread(Vec<UnreadChannel>),
+	Summaries(Vec<ChannelSummary>),
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UnreadChannel {
+	id: u64,
+	/// Messages by other users after the read marker
+	unread: u32,
+	/// Unread messages mentioning the authenticated user by `@username` or `@everyone`
+	mentions: u32,
+}
+
+/// The maximum number of characters of the latest message included in a [`ChannelSummary`]
+const PREVIEW_LENGTH: i32 = 100;
+
+#[derive(Serialize, ToSchema)]
+pub struct ChannelSummary {
+	id: u64,
+	name: String,
+	owner: Uuid,
+	/// The number of participants, not including the owner
+	participant_count: u32,
+	/// The time the latest message was sent, or the channel was created if there are no messages
+	last_message: DateTime<Utc>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	last_message_preview: Option<MessagePreview>,
+	unread: u32,
+	mentions: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MessagePreview {
+	id: u64,
+	sender: Uuid,
+	sender_name: String,
+	/// The first 100 characters of the message
+	content: String,
+}
+
+async fn get_channel_summaries(database: &PgPool, uuid: &Uuid) -> Result<Vec<ChannelSummary>, ApiError> {
+	Ok(query!(
+		r#"WITH ids AS (
+				SELECT id FROM channels WHERE owner = $1
+				UNION SELECT channel_id FROM channel_members WHERE player = $1
+			)
+			SELECT channels.id, channels.name, channels.owner, channels.last_message,
+				(SELECT COUNT(*) FROM channel_members WHERE channel_members.channel_id = channels.id) AS "participant_count!",
+				latest.id AS "latest_id?",
+				latest.sender AS "latest_sender?",
+				latest.sender_name AS "latest_sender_name?",
+				LEFT(latest.content, $2) AS "latest_content?",
+				unread.count AS "unread!",
+				unread.mentions AS "mentions!"
+			FROM ids
+			JOIN channels ON channels.id = ids.id
+			LEFT JOIN direct_messages ON direct_messages.channel_id = channels.id
+			LEFT JOIN channel_read_markers AS markers ON markers.channel_id = channels.id AND markers.player = $1
+			LEFT JOIN LATERAL (
+				SELECT id, sender, sender_name, content FROM messages
+				WHERE messages.channel_id = channels.id ORDER BY id DESC LIMIT 1
+			) AS latest ON true
+			CROSS JOIN LATERAL (
+				SELECT COUNT(*) AS count,
+					COUNT(*) FILTER (WHERE $1 = ANY(mentions)) AS mentions
+				FROM messages
+				WHERE messages.channel_id = channels.id
+					AND messages.id > COALESCE(markers.message, 0)
+					AND messages.sender != $1
+					AND messages.sender NOT IN (SELECT player_b FROM relations WHERE player_a = $1 AND relation = 'blocked')
+			) AS unread
+			WHERE direct_messages.channel_id IS NULL
+			ORDER BY channels.last_message DESC, channels.id DESC"#,
+		uuid,
+		PREVIEW_LENGTH
+	)
+	.fetch_all(database)
+	.await?
+	.into_iter()
+	.map(|channel| ChannelSummary {
+		id: channel.id as u64,
+		name: channel.name,
+		owner: channel.owner,
+		participant_count: channel.participant_count as u32,
+		last_message: channel.last_message.and_utc(),
+		last_message_preview: match (
+			channel.latest_id,
+			channel.latest_sender,
+			channel.latest_sender_name,
+			channel.latest_content,
+		) {
+			(Some(id), Some(sender), Some(sender_name), Some(content)) => Some(MessagePreview {
+				id: id as u64,
+				sender,
+				sender_name,
+				content,
+			}),
+			_ => None,
+		},
+		unread: channel.unread as u32,
+		mentions: channel.mentions as u32,
+	})
+	.collect())
+}
+
+/// Get the direct message channels of the authenticated user, ordered by recent activity
+#[utoipa::path(
+	get,
+	path = "/account/dms",
+	tag = "channel",
+	security(("access_token" = [])),
+	responses((status = OK, body = Vec<DirectMessage>))
+)]
+pub async fn get_dms(
+	State(ApiState { database, .. }): State<ApiState>,
+	Authentication(uuid): Authentication,
+) -> Result<Json<Vec<DirectMessage>>, ApiError> {
+	Ok(Json(
+		query!(
+			r#"SELECT direct_messages.channel_id,
+					CASE WHEN player_a = $1 THEN player_b ELSE player_a END AS "user!",
+					channels.last_message,
+					(SELECT COUNT(*) FROM messages
+						WHERE messages.channel_id = channels.id
+							AND messages.id > COALESCE(markers.message, 0)
+							AND messages.sender != $1
+							AND messages.sender NOT IN (
+								SELECT player_b FROM relations WHERE player_a = $1 AND relation = 'blocked'
+							)) AS "unread!"
+				FROM direct_messages
+				JOIN channels ON channels.id = direct_messages.channel_id
+				LEFT JOIN channel_read_markers AS markers ON markers.channel_id = channels.id AND markers.player = $1
+				WHERE $1 IN (player_a, player_b)
+				ORDER BY channels.last_message DESC"#,
+			uuid
+		)
+		.fetch_all(&database)
+		.await?
+		.into_iter()
+		.map(|dm| DirectMessage {
+			channel: dm.channel_id as u64,
+			user: dm.user,
+			last_message: dm.last_message.and_utc(),
+			unread: dm.unread as u32,
+		})
+		.collect(),
+	))
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct DirectMessage {
+	channel: u64,
+	/// The other user
+	user: Uuid,
+	/// The time the latest message was sent, or the channel was created if there are no messages
+	last_message: DateTime<Utc>,
+	/// Messages by the other user after the read marker
+	unread: u32,
+}
+
+#[utoipa::path(
+	get,
+	path = "/account/relations/friends",
+	tag = "account",
+	security(("access_token" = [])),
+	responses((status = OK, body = Vec<Uuid>))
+)]
 pub async fn get_friends(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -104,6 +388,13 @@ pub async fn get_friends(
 	))
 }
 
+#[utoipa::path(
+	get,
+	path = "/account/relations/blocked",
+	tag = "account",
+	security(("access_token" = [])),
+	responses((status = OK, body = Vec<Uuid>))
+)]
 pub async fn get_blocked(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -118,6 +409,13 @@ pub async fn get_blocked(
 	))
 }
 
+#[utoipa::path(
+	get,
+	path = "/account/relations/requests",
+	tag = "account",
+	security(("access_token" = [])),
+	responses((status = OK, body = HashMap<String, Vec<Uuid>>, description = "Incoming (`in`) and outgoing (`out`) friend requests"))
+)]
 pub async fn get_requests(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -146,7 +444,7 @@ pub async fn get_requests(
 	Ok(Json(map))
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct UserData {
 	user: User,
 	settings: Settings,
@@ -156,6 +454,8 @@ pub struct UserData {
 	images: Vec<ImageExport>,
 }
 
+/// Returns user data in Json format. Access tokens are not included.
+#[utoipa::path(get, path = "/account/data", tag = "account", security(("access_token" = [])), responses((status = OK, body = UserData)))]
 pub async fn get_data(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -181,11 +481,11 @@ impl UserData {
 		for en in relations {
 			map.insert(en.player_b, en.relation);
 		}
-		return Ok(map);
+		Ok(map)
 	}
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct Settings {
 	show_registered: bool,
 	retain_usernames: bool,
@@ -206,7 +506,7 @@ impl Settings {
 	}
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ChannelExport {
 	id: u64,
 	name: String,
@@ -215,7 +515,7 @@ pub struct ChannelExport {
 	messages: Vec<MessageExport>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ChannelSettingsExport {
 	created: DateTime<Utc>,
 	last_updated: DateTime<Utc>,
@@ -223,12 +523,16 @@ pub struct ChannelSettingsExport {
 	persistence: Persistence,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct MessageExport {
 	id: u64,
 	sender_name: String,
 	content: String,
 	send_time: DateTime<Utc>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	edited_at: Option<DateTime<Utc>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	reply_to: Option<u64>,
 }
 
 impl ChannelExport {
@@ -238,9 +542,12 @@ impl ChannelExport {
 		let owned = query!("SELECT * FROM channels WHERE owner = $1", uuid)
 			.fetch_all(database)
 			.await?;
-		let participating = query!("SELECT channels FROM channel_memberships WHERE player = $1", uuid)
-			.fetch_optional(database)
-			.await?;
+		let participating = query!(
+			"SELECT channel_id, name FROM channel_members JOIN channels ON channels.id = channel_id WHERE player = $1",
+			uuid
+		)
+		.fetch_all(database)
+		.await?;
 
 		for en in owned {
 			response.push(ChannelExport {
@@ -258,7 +565,7 @@ impl ChannelExport {
 					.unwrap(),
 				}),
 				participants: Some(
-					query!("SELECT * FROM channel_memberships WHERE $1 = ANY(channels)", &en.id)
+					query!("SELECT player FROM channel_members WHERE channel_id = $1 ORDER BY joined_at", &en.id)
 						.fetch_all(database)
 						.await?
 						.iter()
@@ -269,26 +576,21 @@ impl ChannelExport {
 			});
 		}
 
-		if let Some(rec) = participating {
-			for en in rec.channels {
-				response.push(ChannelExport {
-					id: en as u64,
-					settings: None,
-					participants: None,
-					name: query!("SELECT name FROM channels WHERE id = $1", en)
-						.fetch_one(database)
-						.await?
-						.name,
-					messages: ChannelExport::get_messages(database, uuid, en).await?,
-				});
-			}
+		for en in participating {
+			response.push(ChannelExport {
+				id: en.channel_id as u64,
+				settings: None,
+				participants: None,
+				name: en.name,
+				messages: ChannelExport::get_messages(database, uuid, en.channel_id).await?,
+			});
 		}
-		return Ok(response);
+		Ok(response)
 	}
 
 	async fn get_messages(database: &PgPool, uuid: &Uuid, channel_id: i64) -> Result<Vec<MessageExport>, ApiError> {
 		Ok(query!(
-			"SELECT id, sender_name, content, send_time FROM messages WHERE channel_id = $1 AND sender = $2",
+			"SELECT id, sender_name, content, send_time, edited_at, reply_to FROM messages WHERE channel_id = $1 AND sender = $2",
 			channel_id,
 			uuid
 		)
@@ -300,12 +602,14 @@ impl ChannelExport {
 			sender_name: rec.sender_name.clone(),
 			content: rec.content.clone(),
 			send_time: rec.send_time.and_utc(),
+			edited_at: rec.edited_at.map(|edited_at| edited_at.and_utc()),
+			reply_to: rec.reply_to.map(|id| id as u64),
 		})
 		.collect())
 	}
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ChannelInvitesExport {
 	channel: u64,
 	from: Uuid,
@@ -325,7 +629,7 @@ impl ChannelInvitesExport {
 	}
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ImageExport {
 	id: u64,
 	filename: String,
@@ -349,6 +653,13 @@ impl ImageExport {
 	}
 }
 
+#[utoipa::path(
+	get,
+	path = "/account/settings",
+	tag = "account",
+	security(("access_token" = [])),
+	responses((status = OK, body = Settings))
+)]
 pub async fn get_settings(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -356,7 +667,7 @@ pub async fn get_settings(
 	Ok(Json(Settings::get(&database, &uuid).await?))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct SettingsPatch {
 	show_registered: Option<bool>,
 	retain_usernames: Option<bool>,
@@ -365,6 +676,14 @@ pub struct SettingsPatch {
 	allow_friends_image_access: Option<bool>,
 }
 
+#[utoipa::path(
+	patch,
+	path = "/account/settings",
+	tag = "account",
+	security(("access_token" = [])),
+	request_body = SettingsPatch,
+	responses((status = NO_CONTENT))
+)]
 pub async fn patch_settings(
 	State(ApiState {
 		database, online_users, ..
@@ -396,15 +715,23 @@ pub async fn patch_settings(
 		.execute(&database)
 		.await?;
 
-	if user_settings_patch.show_activity.is_some_and(|value| !value) {
-		if let Some(mut activity) = online_users.get_mut(&uuid) {
-			*activity = None;
-		}
+	if user_settings_patch.show_activity.is_some_and(|value| !value)
+		&& let Some(mut activity) = online_users.get_mut(&uuid)
+	{
+		*activity = None;
 	}
 
 	Ok(StatusCode::NO_CONTENT)
 }
 
+#[utoipa::path(
+	post,
+	path = "/account/username/{username}",
+	tag = "account",
+	params(("username" = String, Path), ("public" = bool, Query)),
+	security(("access_token" = [])),
+	responses((status = NO_CONTENT), (status = NOT_FOUND))
+)]
 pub async fn post_username(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -422,6 +749,14 @@ pub async fn post_username(
 	}
 }
 
+#[utoipa::path(
+	delete,
+	path = "/account/username/{username}",
+	tag = "account",
+	params(("username" = String, Path)),
+	security(("access_token" = [])),
+	responses((status = NO_CONTENT))
+)]
 pub async fn delete_username(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -433,6 +768,14 @@ pub async fn delete_username(
 	Ok(StatusCode::NOT_FOUND)
 }
 
+#[utoipa::path(
+	post,
+	path = "/account/activity",
+	tag = "account",
+	security(("access_token" = [])),
+	request_body = Activity,
+	responses((status = OK))
+)]
 pub async fn post_activity(
 	State(ApiState {
 		online_users,
@@ -472,13 +815,20 @@ pub async fn post_activity(
 	Ok(StatusCode::OK)
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ChannelInvite {
 	id: u64,
 	channel_name: String,
 	from: Uuid,
 }
 
+#[utoipa::path(
+	get,
+	path = "/channels/invites",
+	tag = "channel",
+	security(("access_token" = [])),
+	responses((status = OK, body = Vec<ChannelInvite>))
+)]
 pub async fn get_channel_invites(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -505,16 +855,31 @@ pub async fn get_channel_invites(
 	Ok(Json(invites))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct QueryChannelInvite {
+	#[param(value_type = u64)]
 	id: Id,
 	accept: bool,
 }
 
+/// Accept or ignore a channel invite
+#[utoipa::path(
+	post,
+	path = "/channels/invites",
+	tag = "channel",
+	params(QueryChannelInvite),
+	security(("access_token" = [])),
+	responses(
+		(status = OK),
+		(status = FORBIDDEN, description = "The channel already has the maximum number of participants"),
+	)
+)]
 pub async fn post_channel_invite(
 	State(ApiState {
 		database,
 		socket_sender,
+		config,
 		..
 	}): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -533,21 +898,19 @@ pub async fn post_channel_invite(
 		.await?;
 
 	if accept {
-		query!(
-			r#"INSERT INTO channel_memberships(player, channels)
-			 VALUES ($1, ARRAY [$2::bigint]) 
-			 ON CONFLICT (player) DO UPDATE 
-			 SET channels = ARRAY_APPEND(channel_memberships.channels, $2) 
-			 WHERE channel_memberships.player = $1"#,
-			uuid,
-			&id as _
-		)
-		.execute(&mut *transaction)
-		.await?;
+		channel::lock_channel(&mut transaction, &id).await?;
+		channel::add_participant(&mut *transaction, &id, &uuid).await?;
+		if channel::over_participant_limit(&mut transaction, &id, &config).await? {
+			Err(StatusCode::FORBIDDEN)?
+		}
 	}
 
 	transaction.commit().await?;
 
+	if accept {
+		channel::announce_joins(&database, &socket_sender, &id, &[uuid], &uuid).await;
+	}
+
 	if let Some(socket) = socket_sender.get(&sender.sender) {
 		let _ = socket.send(
 			serde_json::to_string(&json!({
@@ -1,36 +1,81 @@
-use crate::{ApiState, errors::ApiError, extractors::Authentication, id::Id};
+use super::user;
+use crate::{ApiState, config::Config, errors::ApiError, errors::TaskError, extractors::Authentication, id::Id};
 use axum::{
 	Json,
 	extract::{Path, Query, State},
+	response::{AppendHeaders, IntoResponse},
 };
-use chrono::{DateTime, Duration, TimeDelta, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, TimeDelta, Utc};
+use dashmap::DashMap;
 use garde::Validate;
+use log::warn;
+use rand::{Rng, distr::Alphanumeric};
+use regex::Regex;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use serde_json::{Value, json};
-use sqlx::{PgPool, query};
-use std::str::FromStr;
+use serde_json::json;
+use sqlx::{PgConnection, PgExecutor, PgPool, Type, query, query_as, query_scalar};
+use std::{
+	collections::{HashMap, HashSet},
+	sync::LazyLock,
+};
+use tokio::sync::mpsc::UnboundedSender;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 pub struct Channel {
 	id: Id,
 	#[serde(flatten)]
 	channel_data: ChannelData,
+	/// Roles of participants who are not members
+	#[serde(default)]
+	roles: HashMap<Uuid, Role>,
+	/// Whether this is a direct message channel, in which both users are members
+	#[serde(default)]
+	dm: bool,
+	/// Seconds participants have to wait between sending messages, 0 if slow mode is off. The owner is exempt
+	#[serde(default)]
+	slow_mode: u32,
+	/// The maximum number of characters of messages by participants. The owner is exempt, up to 2000 characters
+	max_message_length: u32,
 }
 
-#[derive(Deserialize, Serialize, Validate)]
+#[derive(Deserialize, Serialize, Validate, ToSchema)]
 #[garde(allow_unvalidated)]
 pub struct ChannelData {
 	#[garde(length(min = 1, max = 32))]
 	name: String,
 	#[serde(skip_deserializing)]
+	#[schema(read_only)]
 	owner: Uuid,
 	persistence: Persistence,
+	/// Friends of the owner are added immediately, otherwise a channel invite is sent
 	participants: Vec<Uuid>,
 }
 
-#[derive(Deserialize, Serialize)]
+/// Changes to a channel, fields that shouldn't be changed can be left out
+#[derive(Deserialize, Validate, ToSchema)]
+#[garde(allow_unvalidated)]
+pub struct ChannelPatch {
+	#[garde(inner(length(min = 1, max = 32)))]
+	name: Option<String>,
+	persistence: Option<Persistence>,
+	/// Seconds participants have to wait between sending messages, 0 turns slow mode off
+	#[garde(inner(range(max = MAX_SLOW_MODE_SECONDS)))]
+	slow_mode: Option<u32>,
+	/// The maximum number of characters of messages by participants
+	#[garde(inner(range(min = 1, max = MAX_MESSAGE_LENGTH)))]
+	max_message_length: Option<u32>,
+	/// Friends of the authenticated user are added immediately, otherwise a channel invite is sent
+	#[serde(default, alias = "participants")]
+	add_participants: Vec<Uuid>,
+	/// Participants to remove, which requires the permission to kick them
+	#[serde(default)]
+	remove_participants: Vec<Uuid>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum Persistence {
 	/// Delete messages when the channel is deleted
@@ -38,6 +83,7 @@ pub enum Persistence {
 	/// Delete messages X time after they are sent
 	Duration {
 		#[serde(with = "duration")]
+		#[schema(value_type = u32)]
 		duration: Duration,
 	},
 	/// Delete all but the latest X messages
@@ -47,10 +93,100 @@ pub enum Persistence {
 		count: u32,
 
 		#[serde(with = "duration")]
+		#[schema(value_type = u32)]
 		duration: Duration,
 	},
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize, Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "channel_role", rename_all = "snake_case")]
+pub enum Role {
+	Owner,
+	Moderator,
+	Member,
+	/// Can read, but not send messages or react to them
+	ReadOnly,
+}
+
+#[derive(Clone, Copy)]
+pub enum Permission {
+	SendMessages,
+	Invite,
+	Kick,
+	Rename,
+	ChangePersistence,
+	ChangeLimits,
+	DeleteMessages,
+	PinMessages,
+	ManageRoles,
+}
+
+impl Role {
+	pub fn can(self, permission: Permission) -> bool {
+		use Permission::*;
+		match permission {
+			SendMessages => self != Self::ReadOnly,
+			Invite | Kick | Rename | ChangeLimits | DeleteMessages | PinMessages => {
+				matches!(self, Self::Owner | Self::Moderator)
+			}
+			ChangePersistence | ManageRoles => self == Self::Owner,
+		}
+	}
+
+	/// Whether a user with this role may kick a user with the other role, or delete their messages
+	fn outranks(self, other: Role) -> bool {
+		let rank = |role| match role {
+			Self::Owner => 3,
+			Self::Moderator => 2,
+			Self::Member | Self::ReadOnly => 1,
+		};
+		rank(self) > rank(other)
+	}
+}
+
+impl Channel {
+	fn role(&self, uuid: &Uuid) -> Role {
+		if &self.channel_data.owner == uuid && !self.dm {
+			return Role::Owner;
+		}
+		self.roles.get(uuid).copied().unwrap_or(Role::Member)
+	}
+
+	/// Fails with `400 Bad Request` if the message is longer than the user may send in this channel
+	fn check_length(&self, uuid: &Uuid, content: &str) -> Result<(), StatusCode> {
+		let max = match self.channel_data.owner == *uuid {
+			true => MAX_MESSAGE_LENGTH,
+			false => self.max_message_length,
+		};
+		match content.chars().count() > max as usize {
+			true => Err(StatusCode::BAD_REQUEST),
+			false => Ok(()),
+		}
+	}
+
+	/// Fails with `403 Forbidden` if the user is lacking the permission
+	fn require(&self, uuid: &Uuid, permission: Permission) -> Result<(), StatusCode> {
+		match self.role(uuid).can(permission) {
+			true => Ok(()),
+			false => Err(StatusCode::FORBIDDEN),
+		}
+	}
+
+	/// Fails with `400 Bad Request` if the other user is not in the channel, or `403 Forbidden` if the user may not kick
+	/// them
+	fn check_kick(&self, uuid: &Uuid, other: &Uuid) -> Result<(), StatusCode> {
+		if &self.channel_data.owner != other && !self.channel_data.participants.contains(other) {
+			return Err(StatusCode::BAD_REQUEST);
+		}
+		self.require(uuid, Permission::Kick)?;
+		match self.role(uuid).outranks(self.role(other)) {
+			true => Ok(()),
+			false => Err(StatusCode::FORBIDDEN),
+		}
+	}
+}
+
 impl Persistence {
 	fn id(&self) -> u8 {
 		match self {
@@ -81,10 +217,10 @@ impl Persistence {
 			1 => duration.map(|duration| Self::Duration { duration }),
 			2 => count.map(|count| Self::Count { count }),
 			3 => {
-				if let Some(count) = count {
-					if let Some(duration) = duration {
-						return Some(Self::CountAndDuration { count, duration });
-					}
+				if let Some(count) = count
+					&& let Some(duration) = duration
+				{
+					return Some(Self::CountAndDuration { count, duration });
 				}
 				None
 			}
@@ -100,7 +236,10 @@ async fn get_channel(database: &PgPool, uuid: &Uuid, channel_id: Id) -> Result<C
 		    owner,
 			persistence,
 			persistence_count, 
-			persistence_duration_seconds
+			persistence_duration_seconds,
+			slow_mode_seconds,
+			max_message_length,
+			EXISTS (SELECT 1 FROM direct_messages WHERE channel_id = id) AS "dm!"
 			FROM channels WHERE id = $1"#,
 		&channel_id as _
 	)
@@ -108,35 +247,172 @@ async fn get_channel(database: &PgPool, uuid: &Uuid, channel_id: Id) -> Result<C
 	.await?
 	.ok_or(StatusCode::BAD_REQUEST)?;
 
-	let participants: Vec<Uuid> =
-		query!("SELECT * FROM channel_memberships WHERE $1 = ANY(channels)", &channel_id as _)
-			.fetch_all(database)
-			.await?
-			.iter()
-			.map(|rec| rec.player)
-			.collect();
+	let members = query!(
+		r#"SELECT player, role AS "role: Role" FROM channel_members WHERE channel_id = $1 ORDER BY joined_at, player"#,
+		&channel_id as _
+	)
+	.fetch_all(database)
+	.await?;
+	let participants: Vec<Uuid> = members.iter().map(|rec| rec.player).collect();
 
-	if &channel.owner == uuid || participants.contains(&uuid) {
-		if let Some(persistence) = Persistence::from(
+	if (&channel.owner == uuid || participants.contains(uuid))
+		&& let Some(persistence) = Persistence::from(
 			channel.persistence,
 			channel.persistence_count.map(|i| i as u32),
 			channel.persistence_duration_seconds.map(TimeDelta::seconds),
 		) {
-			return Ok(Channel {
-				id: channel_id,
-				channel_data: ChannelData {
-					name: channel.name,
-					owner: channel.owner,
-					persistence,
-					participants,
-				},
-			});
-		}
+		let roles = members
+			.into_iter()
+			.filter(|rec| rec.role != Role::Member)
+			.map(|rec| (rec.player, rec.role))
+			.collect();
+
+		return Ok(Channel {
+			id: channel_id,
+			channel_data: ChannelData {
+				name: channel.name,
+				owner: channel.owner,
+				persistence,
+				participants,
+			},
+			roles,
+			dm: channel.dm,
+			slow_mode: channel.slow_mode_seconds as u32,
+			max_message_length: channel.max_message_length as u32,
+		});
 	}
 
 	Err(StatusCode::BAD_REQUEST)?
 }
 
+/// Fails with `403 Forbidden` in a direct message channel whose users are no longer friends, either of them blocking the
+/// other also ends the friendship
+async fn require_friends_in_dm(database: &PgPool, channel: &Channel, uuid: &Uuid) -> Result<(), ApiError> {
+	if !channel.dm {
+		return Ok(());
+	}
+	let data = &channel.channel_data;
+	match data
+		.participants
+		.iter()
+		.chain([&data.owner])
+		.find(|player| *player != uuid)
+	{
+		Some(other) if user::are_friends(database, uuid, other).await? => Ok(()),
+		_ => Err(StatusCode::FORBIDDEN)?,
+	}
+}
+
+async fn get_message_sender(database: &PgPool, channel: &Channel, message_id: &Id) -> Result<Uuid, ApiError> {
+	Ok(query!("SELECT sender FROM messages WHERE id = $1 AND channel_id = $2", message_id as _, &channel.id as _)
+		.fetch_optional(database)
+		.await?
+		.ok_or(StatusCode::NOT_FOUND)?
+		.sender)
+}
+
+/// Adds a member to a channel, adding an existing member again has no effect
+pub async fn add_participant<'e>(
+	executor: impl PgExecutor<'e>,
+	channel_id: &Id,
+	player: &Uuid,
+) -> Result<(), sqlx::Error> {
+	query!(
+		"INSERT INTO channel_members (channel_id, player) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+		channel_id as _,
+		player
+	)
+	.execute(executor)
+	.await?;
+	Ok(())
+}
+
+async fn remove_participant<'e>(
+	executor: impl PgExecutor<'e>,
+	channel_id: &Id,
+	player: &Uuid,
+) -> Result<(), sqlx::Error> {
+	query!("DELETE FROM channel_members WHERE channel_id = $1 AND player = $2", channel_id as _, player)
+		.execute(executor)
+		.await?;
+	Ok(())
+}
+
+/// Locks the channel until the end of the transaction, so concurrent changes can't exceed per-channel limits together,
+/// and participants can't leave while ownership is passed on to them
+pub async fn lock_channel(connection: &mut PgConnection, channel_id: &Id) -> Result<(), sqlx::Error> {
+	query!("SELECT id FROM channels WHERE id = $1 FOR UPDATE", channel_id as _)
+		.fetch_one(connection)
+		.await?;
+	Ok(())
+}
+
+/// Whether the channel has more participants than configured, after adding them in a transaction. The channel has to be
+/// locked with [`lock_channel`] before adding them.
+pub async fn over_participant_limit(
+	connection: &mut PgConnection,
+	channel_id: &Id,
+	config: &Config,
+) -> Result<bool, sqlx::Error> {
+	let count =
+		query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM channel_members WHERE channel_id = $1"#, channel_id as _)
+			.fetch_one(connection)
+			.await?;
+	Ok(count > config.limits.channel_participants as i64)
+}
+
+/// Sends a gateway event to the owner and participants of a channel who are online, except the user who caused it
+fn broadcast(socket_sender: &DashMap<Uuid, UnboundedSender<String>>, channel: &Channel, except: &Uuid, event: &str) {
+	let ChannelData {
+		owner, participants, ..
+	} = &channel.channel_data;
+	for uuid in participants.iter().chain([owner]) {
+		if uuid != except
+			&& let Some(socket) = socket_sender.get(uuid)
+		{
+			let _ = socket.send(event.to_string());
+		}
+	}
+}
+
+/// Like [`broadcast`], but also skips users who blocked the sender. Runs after the change was committed, so a failed
+/// lookup is only logged.
+async fn broadcast_from(
+	database: &PgPool,
+	socket_sender: &DashMap<Uuid, UnboundedSender<String>>,
+	channel: &Channel,
+	sender: &Uuid,
+	event: &str,
+) {
+	let Ok(blocked_by) = user::blocked_by(database, sender).await else {
+		warn!("Failed to broadcast a message from {sender} to channel {}", *channel.id);
+		return;
+	};
+	let ChannelData {
+		owner, participants, ..
+	} = &channel.channel_data;
+	for uuid in participants.iter().chain([owner]) {
+		if uuid != sender
+			&& !blocked_by.contains(uuid)
+			&& let Some(socket) = socket_sender.get(uuid)
+		{
+			let _ = socket.send(event.to_string());
+		}
+	}
+}
+
+#[utoipa::path(
+	get,
+	path = "/channel/{id}",
+	operation_id = "get_channel",
+	tag = "channel",
+	params(("id" = u64, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = OK, body = Channel),
+		(status = BAD_REQUEST, description = "The channel does not exist or the authenticated user does not participate in it"),
+	)
+)]
 pub async fn get(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -145,46 +421,96 @@ pub async fn get(
 	Ok(Json(get_channel(&database, &uuid, channel_id).await?))
 }
 
+/// Leave the channel, or delete it if the authenticated user owns it. The owner can also leave, passing ownership on to
+/// the longest-standing participant, in which case the channel is only deleted if there are no participants.
+#[utoipa::path(
+	delete,
+	path = "/channel/{id}",
+	operation_id = "delete_channel",
+	tag = "channel",
+	params(("id" = u64, Path), DeleteChannel),
+	security(("access_token" = [])),
+	responses(
+		(status = OK),
+		(status = BAD_REQUEST, description = "The channel does not exist or the authenticated user does not participate in it"),
+		(status = FORBIDDEN, description = "The channel is a direct message channel"),
+	)
+)]
 pub async fn delete(
-	State(ApiState { database, .. }): State<ApiState>,
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
 	Authentication(uuid): Authentication,
 	Path(channel_id): Path<Id>,
+	Query(DeleteChannel { leave }): Query<DeleteChannel>,
 ) -> Result<StatusCode, ApiError> {
 	let channel = get_channel(&database, &uuid, channel_id).await?;
+	if channel.dm {
+		Err(StatusCode::FORBIDDEN)?
+	}
 
 	if channel.channel_data.owner == uuid {
-		query!("DELETE FROM channels WHERE id = $1", &channel.id as _)
-			.execute(&database)
-			.await?;
-		query!(
-			"UPDATE channel_memberships SET channels = array_remove(channels, $1) WHERE $1 = ANY(channels)",
-			&channel.id as _
-		)
-		.execute(&database)
-		.await?;
+		let mut transaction = database.begin().await?;
+		// Locks the channel, so no participant can leave between choosing them and passing ownership on to them
+		lock_channel(&mut transaction, &channel.id).await?;
+		let successor = match leave {
+			true => successor(&mut *transaction, &channel.id).await?,
+			false => None,
+		};
+
+		if let Some(owner) = successor {
+			transfer_ownership(&mut transaction, &channel.id, &uuid, &owner, false).await?;
+			transaction.commit().await?;
+			announce_owner_change(&database, &socket_sender, Id::from(*channel.id), &owner, &uuid).await;
+			announce_leave(&database, &socket_sender, &channel.id, &owner, &uuid, None).await;
+		} else {
+			query!("DELETE FROM channels WHERE id = $1", &channel.id as _)
+				.execute(&mut *transaction)
+				.await?;
+			transaction.commit().await?;
+			let message = serde_json::to_string(&json!({
+				"target": "channel_deleted",
+				"channel": &channel.id
+			}))
+			.unwrap();
+			broadcast(&socket_sender, &channel, &uuid, &message);
+		}
 	} else {
-		query!(
-			"UPDATE channel_memberships SET channels = array_remove(channels, $2) WHERE player = $1 AND $2 = ANY(channels)",
-			&uuid,
-			&channel.id as _
-		)
-		.execute(&database)
-		.await?;
+		let mut transaction = database.begin().await?;
+		lock_channel(&mut transaction, &channel.id).await?;
+		remove_participant(&mut *transaction, &channel.id, &uuid).await?;
+		transaction.commit().await?;
+		announce_leave(&database, &socket_sender, &channel.id, &channel.channel_data.owner, &uuid, None).await;
 	}
 
 	Ok(StatusCode::OK)
 }
 
+#[utoipa::path(
+	post,
+	path = "/channel",
+	operation_id = "create_channel",
+	tag = "channel",
+	security(("access_token" = [])),
+	request_body = ChannelData,
+	responses((status = OK, body = String, content_type = "text/plain", description = "The id of the new channel"))
+)]
 pub async fn post(
 	State(ApiState {
 		database,
 		socket_sender,
+		config,
 		..
 	}): State<ApiState>,
 	Authentication(owner): Authentication,
 	Json(channel_data): Json<ChannelData>,
 ) -> Result<String, ApiError> {
 	channel_data.validate()?;
+	if channel_data.participants.len() > config.limits.channel_participants as usize {
+		Err(StatusCode::BAD_REQUEST)?
+	}
 
 	let id = Id::new();
 	let persistence = channel_data.persistence.id();
@@ -222,19 +548,16 @@ pub async fn post(
 			.into_iter()
 			.map(|r| r.player_b)
 			.collect();
+	let blocks = user::blocks(&database, &owner).await?;
+	let mut joined = Vec::new();
+	let mut invited = Vec::new();
 	for uuid in participants {
+		if blocks.contains(&uuid) {
+			continue;
+		}
 		if friends.contains(&uuid) {
-			query!(
-				r#"INSERT INTO channel_memberships(player, channels)
-			 VALUES ($1, ARRAY [$2::bigint]) 
-			 ON CONFLICT (player) DO UPDATE 
-			 SET channels = ARRAY_APPEND(channel_memberships.channels, $2) 
-			 WHERE channel_memberships.player = $1"#,
-				uuid,
-				&id as _
-			)
-			.execute(&mut *transaction)
-			.await?;
+			add_participant(&mut *transaction, &id, &uuid).await?;
+			joined.push(uuid);
 		} else {
 			query!(
 				"INSERT INTO channel_invites (channel, player, sender) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
@@ -244,170 +567,303 @@ pub async fn post(
 			)
 			.execute(&mut *transaction)
 			.await?;
-			if let Some(socket) = socket_sender.get(&uuid) {
-				let _ = socket.send(
-					serde_json::to_string(&json!({
-						"target": "channel_invite",
-						"channel": &id,
-						"channel_name": &channel_data.name,
-						"from": &channel_data.owner
-					}))
-					.unwrap(),
-				);
-			}
+			invited.push(uuid);
 		}
 	}
 
 	transaction.commit().await?;
+	for uuid in &invited {
+		if let Some(socket) = socket_sender.get(uuid) {
+			let _ = socket.send(
+				serde_json::to_string(&json!({
+					"target": "channel_invite",
+					"channel": &id,
+					"channel_name": &channel_data.name,
+					"from": &channel_data.owner
+				}))
+				.unwrap(),
+			);
+		}
+	}
+	announce_joins(&database, &socket_sender, &id, &joined, &owner).await;
 
 	Ok(id.to_string())
 }
 
+/// Update channel settings, fields that shouldn't be changed can be left out
+#[utoipa::path(
+	patch,
+	path = "/channel/{id}",
+	operation_id = "patch_channel",
+	tag = "channel",
+	params(("id" = u64, Path)),
+	security(("access_token" = [])),
+	request_body = ChannelPatch,
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist, the authenticated user does not participate in it, the body is invalid, a user to remove does not participate, or the channel would have too many participants"),
+		(status = FORBIDDEN, description = "The role of the authenticated user does not allow one of the changes"),
+	)
+)]
 pub async fn patch(
 	State(ApiState {
 		database,
 		socket_sender,
+		config,
 		..
 	}): State<ApiState>,
 	Authentication(uuid): Authentication,
 	Path(channel_id): Path<Id>,
-	Json(value): Json<Value>,
+	Json(channel_patch): Json<ChannelPatch>,
 ) -> Result<StatusCode, ApiError> {
+	channel_patch.validate()?;
+	let ChannelPatch {
+		name,
+		persistence,
+		slow_mode,
+		max_message_length,
+		add_participants,
+		remove_participants,
+	} = channel_patch;
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+
+	if name.is_some() {
+		channel.require(&uuid, Permission::Rename)?;
+	}
+	if persistence.is_some() {
+		channel.require(&uuid, Permission::ChangePersistence)?;
+	}
+	if slow_mode.is_some() || max_message_length.is_some() {
+		channel.require(&uuid, Permission::ChangeLimits)?;
+	}
+	if !add_participants.is_empty() {
+		channel.require(&uuid, Permission::Invite)?;
+	}
+	for player in &remove_participants {
+		if add_participants.contains(player) {
+			Err(StatusCode::BAD_REQUEST)?
+		}
+		channel.check_kick(&uuid, player)?;
+	}
+	let persistence: &Persistence = persistence.as_ref().unwrap_or(&channel.channel_data.persistence);
+
 	let mut transaction = database.begin().await?;
+	lock_channel(&mut transaction, &channel.id).await?;
 
-	let channel = query!(
-		r#"SELECT id,
-			name,
-		    owner,
-			persistence,
-			persistence_count, 
-			persistence_duration_seconds
-			FROM channels WHERE id = $1"#,
-		channel_id as _
+	let persistence_id = persistence.id() as i16;
+	let persistence_count = persistence.count();
+	let persistence_duration_seconds = persistence.duration().map(|duration| duration.num_seconds());
+	query!(
+		r#"UPDATE channels SET
+				name = coalesce($1, name),
+				persistence = coalesce($2, persistence),
+				persistence_count = coalesce($3, persistence_count),
+				persistence_duration_seconds = coalesce($4, persistence_duration_seconds),
+				slow_mode_seconds = coalesce($5, slow_mode_seconds),
+				max_message_length = coalesce($6, max_message_length),
+				last_updated = LOCALTIMESTAMP
+				WHERE id = $7"#,
+		name,
+		persistence_id as _,
+		persistence_count.map(|c| *c as i32),
+		persistence_duration_seconds,
+		slow_mode.map(|seconds| seconds as i32),
+		max_message_length.map(|length| length as i16),
+		&channel.id as _
 	)
-	.fetch_optional(&mut *transaction)
-	.await?
-	.ok_or(StatusCode::BAD_REQUEST)?;
+	.execute(&mut *transaction)
+	.await?;
+	let name = name.unwrap_or(channel.channel_data.name.clone());
 
-	if channel.owner == uuid {
-		if let Some(mut persistence) = Persistence::from(
-			channel.persistence,
-			channel.persistence_count.map(|i| i as u32),
-			channel.persistence_duration_seconds.map(TimeDelta::seconds),
-		) {
-			let mut name = channel.name;
-			let mut participants: Vec<Uuid> = Vec::new();
-			if let Some(val) = value.get("name") {
-				name = val.as_str().ok_or(StatusCode::BAD_REQUEST)?.to_string()
-			}
-			if let Some(uuids) = value.get("participants") {
-				let vec = uuids.as_array().unwrap();
-				for val in vec {
-					let str = val.as_str().ok_or(StatusCode::BAD_REQUEST)?;
-					let uuid = Uuid::from_str(str).map_err(|_| StatusCode::BAD_REQUEST)?;
-					participants.push(uuid);
-				}
-			}
-			if let Some(val) = value.get("persistence") {
-				persistence = serde_json::from_value(val.clone()).map_err(|_| StatusCode::BAD_REQUEST)?;
-			}
+	for player in &remove_participants {
+		remove_participant(&mut *transaction, &channel.id, player).await?;
+	}
 
-			let persistence_id = persistence.id() as i16;
-			let persistence_count = persistence.count();
-			let persistence_duration_seconds = persistence.duration().map(|duration| duration.num_seconds());
+	// Friends of whoever adds the participants join immediately, everyone else is invited by them
+	let friends: Vec<Uuid> =
+		query!("SELECT player_b FROM relations WHERE relation = 'friend' AND player_a = $1", &uuid)
+			.fetch_all(&database)
+			.await?
+			.into_iter()
+			.map(|r| r.player_b)
+			.collect();
+	// Users in a block with whoever adds the participants are skipped silently
+	let blocks = user::blocks(&database, &uuid).await?;
+	// Tried to use batch insert via UNNEST here, however Postgres was not cooperating.
+	// Given that this isn't likely to be more then a few players, the cost here is negligible for the time being.
+	let mut joined = Vec::new();
+	let mut invited = Vec::new();
+	for player in add_participants {
+		if blocks.contains(&player) {
+			continue;
+		}
+		if friends.contains(&player) {
+			add_participant(&mut *transaction, &channel.id, &player).await?;
+			joined.push(player);
+		} else {
 			query!(
-				r#"UPDATE channels SET
-					name = coalesce($1, name),
-					persistence = coalesce($2, persistence),
-					persistence_count = coalesce($3, persistence_count),
-					persistence_duration_seconds = coalesce($4, persistence_duration_seconds),
-					last_updated = LOCALTIMESTAMP
-					WHERE id = $5"#,
-				name,
-				persistence_id as _,
-				persistence_count.map(|c| *c as i32),
-				persistence_duration_seconds,
-				channel_id as _
+				"INSERT INTO channel_invites (channel, player, sender) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+				&channel.id as _,
+				player,
+				&uuid
 			)
 			.execute(&mut *transaction)
 			.await?;
+			invited.push(player);
+		}
+	}
 
-			let friends: Vec<Uuid> =
-				query!("SELECT player_b FROM relations WHERE relation = 'friend' AND player_a = $1", &channel.owner)
-					.fetch_all(&database)
-					.await?
-					.into_iter()
-					.map(|r| r.player_b)
-					.collect();
-			// Tried to use batch insert via UNNEST here, however Postgres was not cooperating.
-			// Given that this isn't likely to be more then a few players, the cost here is negligible for the time being.
-			for player in participants {
-				if friends.contains(&player) {
-					query!(
-						r#"INSERT INTO channel_memberships(player, channels)
-					 VALUES ($1, ARRAY [$2::bigint]) 
-					 ON CONFLICT (player) DO UPDATE 
-					 SET channels = ARRAY_APPEND(channel_memberships.channels, $2) 
-					 WHERE channel_memberships.player = $1"#,
-						player,
-						channel_id as _
-					)
-					.execute(&mut *transaction)
-					.await?;
-				} else {
-					query!(
-						"INSERT INTO channel_invites (channel, player, sender) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
-						channel_id as _,
-						player,
-						&channel.owner
-					)
-					.execute(&mut *transaction)
-					.await?;
-					if let Some(socket) = socket_sender.get(&player) {
-						let _ = socket.send(
-							serde_json::to_string(&json!({
-								"target": "channel_invite",
-								"channel": &channel_id,
-								"channel_name": name.clone(),
-								"from": &channel.owner
-							}))
-							.unwrap(),
-						);
-					}
-				}
-			}
+	if over_participant_limit(&mut transaction, &channel.id, &config).await? {
+		Err(StatusCode::BAD_REQUEST)?
+	}
 
-			transaction.commit().await?;
-			return Ok(StatusCode::NO_CONTENT);
+	transaction.commit().await?;
+
+	// Only sent once committed, the invites don't exist if the limit was exceeded
+	for player in &invited {
+		if let Some(socket) = socket_sender.get(player) {
+			let _ = socket.send(
+				serde_json::to_string(&json!({
+					"target": "channel_invite",
+					"channel": &channel.id,
+					"channel_name": name.clone(),
+					"from": &uuid
+				}))
+				.unwrap(),
+			);
 		}
 	}
 
-	Err(StatusCode::BAD_REQUEST)?
+	for player in &remove_participants {
+		announce_leave(&database, &socket_sender, &channel.id, &channel.channel_data.owner, player, Some(&uuid)).await;
+	}
+	announce_joins(&database, &socket_sender, &channel.id, &joined, &uuid).await;
+
+	Ok(StatusCode::NO_CONTENT)
 }
 
+/// Send a message to a channel
+#[utoipa::path(
+	post,
+	path = "/channel/{id}",
+	tag = "channel",
+	params(("id" = u64, Path)),
+	security(("access_token" = [])),
+	request_body = PostMessage,
+	responses(
+		(status = OK, body = String, content_type = "text/plain", description = "The id of the new message"),
+		(status = BAD_REQUEST, description = "The channel does not exist, the authenticated user does not participate in it, the message is too long, the message replied to is not in this channel, or an attachment is invalid"),
+		(status = FORBIDDEN, description = "The authenticated user is read-only in this channel, or is no longer friends with the other user of a direct message channel"),
+		(status = TOO_MANY_REQUESTS, headers(("Retry-After" = u64, description = "Seconds until the authenticated user can send a message again")), description = "Slow mode is on and the authenticated user sent a message too recently"),
+	)
+)]
 pub async fn post_channel(
 	State(ApiState {
 		database,
-		online_users,
 		socket_sender,
 		..
 	}): State<ApiState>,
 	Authentication(uuid): Authentication,
 	Path(channel_id): Path<Id>,
-	Json(PostMessage { content, display_name }): Json<PostMessage>,
+	Json(PostMessage {
+		content,
+		display_name,
+		reply_to,
+		attachments,
+	}): Json<PostMessage>,
 ) -> Result<String, ApiError> {
 	let channel = get_channel(&database, &uuid, channel_id).await?;
+	channel.require(&uuid, Permission::SendMessages)?;
+	require_friends_in_dm(&database, &channel, &uuid).await?;
+	channel.check_length(&uuid, &content)?;
+
+	let reply = match &reply_to {
+		Some(reply_to) => {
+			let replied = query!(
+				"SELECT sender, sender_name, content FROM messages WHERE id = $1 AND channel_id = $2",
+				reply_to as _,
+				&channel.id as _
+			)
+			.fetch_optional(&database)
+			.await?
+			.ok_or(StatusCode::BAD_REQUEST)?;
+			Some(Reply {
+				id: **reply_to,
+				sender: Some(replied.sender),
+				sender_name: Some(replied.sender_name),
+				content: Some(replied.content),
+			})
+		}
+		None => None,
+	};
+
+	let mut attachments: Vec<i64> = attachments.iter().map(|id| **id as i64).collect();
+	let mut seen = HashSet::new();
+	attachments.retain(|id| seen.insert(*id));
+	if attachments.len() > MAX_ATTACHMENTS {
+		Err(StatusCode::BAD_REQUEST)?
+	}
+	let owned = query_scalar!(
+		r#"SELECT COUNT(*) AS "count!" FROM images WHERE id = ANY($1) AND player = $2"#,
+		&attachments,
+		uuid
+	)
+	.fetch_one(&database)
+	.await?;
+	if owned != attachments.len() as i64 {
+		Err(StatusCode::BAD_REQUEST)?
+	}
+
+	let mentions = parse_mentions(&database, &channel, &uuid, &content).await?;
 
 	let mut transaction = database.begin().await?;
 
+	// The owner is not a member, and exempt from slow mode anyway
+	if channel.channel_data.owner != uuid {
+		let sent = query!(
+			r#"UPDATE channel_members SET last_message = LOCALTIMESTAMP
+				WHERE channel_id = $1 AND player = $2
+					AND (last_message IS NULL OR last_message + make_interval(secs => $3) <= LOCALTIMESTAMP)"#,
+			&channel.id as _,
+			uuid,
+			channel.slow_mode as f64
+		)
+		.execute(&mut *transaction)
+		.await?
+		.rows_affected();
+		if sent == 0 {
+			let retry_after = query_scalar!(
+				r#"SELECT CEIL(EXTRACT(EPOCH FROM last_message + make_interval(secs => $3) - LOCALTIMESTAMP))::BIGINT AS "retry_after!"
+					FROM channel_members WHERE channel_id = $1 AND player = $2"#,
+				&channel.id as _,
+				uuid,
+				channel.slow_mode as f64
+			)
+			.fetch_one(&mut *transaction)
+			.await?;
+			Err((StatusCode::TOO_MANY_REQUESTS, [("Retry-After", retry_after.max(1).to_string())]).into_response())?
+		}
+	}
+
 	let id = Id::new();
 	query!(
-		"INSERT INTO messages (id, channel_id, sender, sender_name, content) VALUES ($1, $2, $3, $4, $5)",
+		"INSERT INTO messages (id, channel_id, sender, sender_name, content, reply_to, mentions) VALUES ($1, $2, $3, $4, $5, $6, $7)",
 		&id as _,
 		&channel.id as _,
 		uuid,
 		display_name,
-		content
+		content,
+		&reply_to as _,
+		&mentions
+	)
+	.execute(&mut *transaction)
+	.await?;
+
+	query!(
+		r#"INSERT INTO message_attachments (message, image, position)
+			SELECT $1, image, position - 1 FROM UNNEST($2::BIGINT[]) WITH ORDINALITY AS attachments(image, position)"#,
+		&id as _,
+		&attachments
 	)
 	.execute(&mut *transaction)
 	.await?;
@@ -416,6 +872,9 @@ pub async fn post_channel(
 		.execute(&mut *transaction)
 		.await?;
 
+	// Count based persistence is enforced immediately, the background task only catches up on durations
+	delete_expired_messages(&mut *transaction, Some(&channel.id)).await?;
+
 	transaction.commit().await?;
 	let message = serde_json::to_string(&json!({
 		"target": "chat_message",
@@ -423,110 +882,1669 @@ pub async fn post_channel(
 		"id": &id,
 		"sender": &uuid,
 		"sender_name": display_name,
-		"content": content
+		"content": content,
+		"reply_to": reply,
+		"mentions": mentions,
+		"attachments": attachments
 	}))
 	.unwrap();
+	broadcast_from(&database, &socket_sender, &channel, &uuid, &message).await;
+	notify_mentions(&socket_sender, &channel, &id, &uuid, &display_name, &content, &mentions);
 
-	if uuid != channel.channel_data.owner && online_users.contains_key(&channel.channel_data.owner) {
-		socket_sender
-			.get(&channel.channel_data.owner)
-			.ok_or(StatusCode::INTERNAL_SERVER_ERROR)?
-			.value()
-			.send(message.clone())
-			.unwrap();
-	}
-	for participant in channel.channel_data.participants {
-		if participant != uuid && online_users.contains_key(&participant) {
-			socket_sender
-				.get(&participant)
-				.ok_or(StatusCode::INTERNAL_SERVER_ERROR)?
-				.value()
-				.send(message.clone())
-				.unwrap();
+	Ok(id.to_string())
+}
+
+/// Sends a `mention` event to each of the mentioned users
+fn notify_mentions(
+	socket_sender: &DashMap<Uuid, UnboundedSender<String>>,
+	channel: &Channel,
+	id: &Id,
+	sender: &Uuid,
+	sender_name: &str,
+	content: &str,
+	mentions: &[Uuid],
+) {
+	let mention = serde_json::to_string(&json!({
+		"target": "mention",
+		"channel": &channel.id,
+		"channel_name": &channel.channel_data.name,
+		"id": id,
+		"sender": sender,
+		"sender_name": sender_name,
+		"content": content
+	}))
+	.unwrap();
+	for player in mentions {
+		if let Some(socket) = socket_sender.get(player) {
+			let _ = socket.send(mention.clone());
 		}
 	}
-	Ok(id.to_string())
 }
 
-pub async fn get_messages(
-	State(ApiState { database, .. }): State<ApiState>,
-	Authentication(uuid): Authentication,
-	Path(channel_id): Path<Id>,
-	Query(QueryBefore { before }): Query<QueryBefore>,
-) -> Result<Json<Vec<Message>>, ApiError> {
-	let channel = get_channel(&database, &uuid, channel_id).await?;
+/// The maximum length of any message, in characters
+const MAX_MESSAGE_LENGTH: u32 = 2000;
+/// The longest slow mode interval, 6 hours
+const MAX_SLOW_MODE_SECONDS: u32 = 6 * 60 * 60;
 
-	let records = query!(
-		"SELECT id, channel_id, sender, sender_name, content, send_time FROM messages WHERE channel_id = $1 AND send_time < $2 LIMIT 50",
-		&channel.id as _,
-		before.unwrap_or(Utc::now()) as _
-	)
-	.fetch_all(&database)
-	.await?;
+/// The maximum number of images attached to a message
+const MAX_ATTACHMENTS: usize = 10;
+
+/// Matches `@username` and `@everyone`, as long as the `@` does not directly follow a word character
+static MENTION_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^|[^A-Za-z0-9_])@([A-Za-z0-9_]+)").unwrap());
 
-	let mut messages: Vec<Message> = records
+/// Finds the participants of a channel mentioned in a message. The sender and users who blocked them are never
+/// mentioned.
+async fn parse_mentions(
+	database: &PgPool,
+	channel: &Channel,
+	sender: &Uuid,
+	content: &str,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+	let names: HashSet<String> = MENTION_REGEX
+		.captures_iter(content)
+		.map(|captures| captures[1].to_lowercase())
+		.collect();
+	if names.is_empty() {
+		return Ok(Vec::new());
+	}
+	let everyone = names.contains("everyone");
+	let names: Vec<String> = names.into_iter().collect();
+
+	let ChannelData {
+		owner, participants, ..
+	} = &channel.channel_data;
+	let blocked_by = user::blocked_by(database, sender).await?;
+	let candidates: Vec<Uuid> = participants
 		.iter()
-		.map(|m| Message {
-			id: m.id as u64,
-			channel_id: m.channel_id as u64,
-			sender: m.sender,
-			sender_name: m.sender_name.clone(),
-			content: m.content.clone(),
-			timestamp: m.send_time.and_utc(),
-		})
+		.chain([owner])
+		.filter(|uuid| *uuid != sender && !blocked_by.contains(uuid))
+		.copied()
 		.collect();
-	messages.sort_by_key(|m| m.timestamp);
 
-	Ok(Json(messages))
+	query_scalar!(
+		"SELECT uuid FROM players WHERE uuid = ANY($1) AND ($2 OR lower(username) = ANY($3))",
+		&candidates,
+		everyone,
+		&names
+	)
+	.fetch_all(database)
+	.await
 }
 
-pub async fn remove_user(
-	State(ApiState { database, .. }): State<ApiState>,
-	Authentication(owner): Authentication,
-	Path(channel_id): Path<Id>,
-	Query(uuid): Query<Uuid>,
+/// Edit a message sent by the authenticated user
+#[utoipa::path(
+	patch,
+	path = "/channel/{id}/messages/{message_id}",
+	tag = "channel",
+	params(("id" = u64, Path), ("message_id" = u64, Path)),
+	security(("access_token" = [])),
+	request_body = EditMessage,
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist, the authenticated user does not participate in it, or the message is too long"),
+		(status = FORBIDDEN, description = "The message was not sent by the authenticated user, they are read-only in this channel, or they are no longer friends with the other user of a direct message channel"),
+		(status = NOT_FOUND, description = "The message does not exist in this channel"),
+	)
+)]
+pub async fn patch_message(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path((channel_id, message_id)): Path<(Id, Id)>,
+	Json(EditMessage { content }): Json<EditMessage>,
 ) -> Result<StatusCode, ApiError> {
 	let channel = get_channel(&database, &uuid, channel_id).await?;
-	if channel.channel_data.owner == owner {
-		query!(
-			"UPDATE channel_memberships SET channels = ARRAY_REMOVE(channels, $1) WHERE player = $2",
-			&channel.id as _,
-			uuid
-		)
-		.execute(&database)
-		.await?;
-		return Ok(StatusCode::OK);
+
+	let sender = get_message_sender(&database, &channel, &message_id).await?;
+	if sender != uuid {
+		Err(StatusCode::FORBIDDEN)?
 	}
-	Ok(StatusCode::BAD_REQUEST)
-}
+	channel.require(&uuid, Permission::SendMessages)?;
+	require_friends_in_dm(&database, &channel, &uuid).await?;
+	channel.check_length(&uuid, &content)?;
+	let mentions = parse_mentions(&database, &channel, &uuid, &content).await?;
 
-/*pub async fn report_message(State(ApiState { database, .. }): State<ApiState>,
-	Authentication(uuid): Authentication,
-	Path(message_id): Path<Id>,
-) -> Result<StatusCode, ApiError> {
-	todo!("Store reports somewhere and notify us of them!");
-	//Ok(StatusCode::BAD_REQUEST)
-}*/
+	let edited = query!(
+		r#"UPDATE messages SET content = $1, mentions = $3, edited_at = LOCALTIMESTAMP
+			FROM (SELECT mentions FROM messages WHERE id = $2 FOR UPDATE) AS previous
+			WHERE messages.id = $2
+			RETURNING messages.sender_name, messages.edited_at AS "edited_at!", previous.mentions AS previous_mentions"#,
+		content,
+		&message_id as _,
+		&mentions
+	)
+	.fetch_one(&database)
+	.await?;
 
-#[derive(Serialize)]
-pub struct Message {
-	id: u64,
-	channel_id: u64,
-	sender: Uuid,
-	sender_name: String,
-	content: String,
-	timestamp: DateTime<Utc>,
+	let message = serde_json::to_string(&json!({
+		"target": "message_update",
+		"channel": &channel.id,
+		"id": &message_id,
+		"content": content,
+		"mentions": mentions,
+		"edited_at": edited.edited_at.and_utc()
+	}))
+	.unwrap();
+	broadcast_from(&database, &socket_sender, &channel, &uuid, &message).await;
+
+	// Users who were mentioned before have been notified already
+	let mentioned: Vec<Uuid> = (mentions.iter())
+		.filter(|player| !edited.previous_mentions.contains(player))
+		.copied()
+		.collect();
+	notify_mentions(&socket_sender, &channel, &message_id, &uuid, &edited.sender_name, &content, &mentioned);
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete a message sent by the authenticated user, or a message of a user they outrank in a channel they own or
+/// moderate
+#[utoipa::path(
+	delete,
+	path = "/channel/{id}/messages/{message_id}",
+	tag = "channel",
+	params(("id" = u64, Path), ("message_id" = u64, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist or the authenticated user does not participate in it"),
+		(status = FORBIDDEN, description = "The message was neither sent by the authenticated user, nor are they the owner or a moderator of the channel outranking its sender"),
+		(status = NOT_FOUND, description = "The message does not exist in this channel"),
+	)
+)]
+pub async fn delete_message(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path((channel_id, message_id)): Path<(Id, Id)>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+
+	let sender = get_message_sender(&database, &channel, &message_id).await?;
+	let role = channel.role(&uuid);
+	if sender != uuid && !(role.can(Permission::DeleteMessages) && role.outranks(channel.role(&sender))) {
+		Err(StatusCode::FORBIDDEN)?
+	}
+
+	let mut transaction = database.begin().await?;
+	let unpinned = query!("DELETE FROM message_pins WHERE message = $1", &message_id as _)
+		.execute(&mut *transaction)
+		.await?
+		.rows_affected();
+	query!("DELETE FROM messages WHERE id = $1", &message_id as _)
+		.execute(&mut *transaction)
+		.await?;
+	transaction.commit().await?;
+
+	let message = serde_json::to_string(&json!({
+		"target": "message_delete",
+		"channel": &channel.id,
+		"id": &message_id
+	}))
+	.unwrap();
+	broadcast(&socket_sender, &channel, &uuid, &message);
+
+	if unpinned > 0 {
+		let message = serde_json::to_string(&json!({
+			"target": "pins_update",
+			"channel": &channel.id,
+			"message": &message_id,
+			"player": &uuid,
+			"pinned": false
+		}))
+		.unwrap();
+		broadcast(&socket_sender, &channel, &uuid, &message);
+	}
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// The maximum number of distinct emoji a message can be reacted with
+const MAX_REACTIONS_PER_MESSAGE: i64 = 20;
+
+/// React to a message, reacting with the same emoji twice has no effect
+#[utoipa::path(
+	put,
+	path = "/channel/{id}/messages/{message_id}/reactions/{emoji}",
+	tag = "channel",
+	params(("id" = u64, Path), ("message_id" = u64, Path), ("emoji" = String, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist, the authenticated user does not participate in it, the emoji is invalid, or the message already has the maximum number of distinct reactions"),
+		(status = FORBIDDEN, description = "The authenticated user is read-only in this channel, or is no longer friends with the other user of a direct message channel"),
+		(status = NOT_FOUND, description = "The message does not exist in this channel"),
+	)
+)]
+pub async fn put_reaction(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path((channel_id, message_id, emoji)): Path<(Id, Id, String)>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	channel.require(&uuid, Permission::SendMessages)?;
+	require_friends_in_dm(&database, &channel, &uuid).await?;
+	get_message_sender(&database, &channel, &message_id).await?;
+	if !valid_emoji(&emoji) {
+		Err(StatusCode::BAD_REQUEST)?
+	}
+
+	let mut transaction = database.begin().await?;
+
+	// Locks the message, so concurrent reactions can't exceed the limit together
+	query!("SELECT id FROM messages WHERE id = $1 FOR UPDATE", &message_id as _)
+		.fetch_optional(&mut *transaction)
+		.await?
+		.ok_or(StatusCode::NOT_FOUND)?;
+
+	let distinct = query!(
+		r#"SELECT COUNT(DISTINCT emoji) AS "distinct!", COALESCE(bool_or(emoji = $2), false) AS "exists!" FROM message_reactions WHERE message = $1"#,
+		&message_id as _,
+		emoji
+	)
+	.fetch_one(&mut *transaction)
+	.await?;
+	if !distinct.exists && distinct.distinct >= MAX_REACTIONS_PER_MESSAGE {
+		Err(StatusCode::BAD_REQUEST)?
+	}
+
+	let inserted = query!(
+		"INSERT INTO message_reactions (message, player, emoji) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+		&message_id as _,
+		uuid,
+		emoji
+	)
+	.execute(&mut *transaction)
+	.await?
+	.rows_affected();
+
+	transaction.commit().await?;
+
+	if inserted > 0 {
+		let message = serde_json::to_string(&json!({
+			"target": "reaction_add",
+			"channel": &channel.id,
+			"message": &message_id,
+			"player": &uuid,
+			"emoji": emoji
+		}))
+		.unwrap();
+		broadcast_from(&database, &socket_sender, &channel, &uuid, &message).await;
+	}
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove the authenticated user's reaction from a message
+#[utoipa::path(
+	delete,
+	path = "/channel/{id}/messages/{message_id}/reactions/{emoji}",
+	tag = "channel",
+	params(("id" = u64, Path), ("message_id" = u64, Path), ("emoji" = String, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist or the authenticated user does not participate in it"),
+		(status = NOT_FOUND, description = "The message does not exist in this channel"),
+	)
+)]
+pub async fn delete_reaction(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path((channel_id, message_id, emoji)): Path<(Id, Id, String)>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	get_message_sender(&database, &channel, &message_id).await?;
+
+	let deleted = query!(
+		"DELETE FROM message_reactions WHERE message = $1 AND player = $2 AND emoji = $3",
+		&message_id as _,
+		uuid,
+		emoji
+	)
+	.execute(&database)
+	.await?
+	.rows_affected();
+
+	if deleted > 0 {
+		let message = serde_json::to_string(&json!({
+			"target": "reaction_remove",
+			"channel": &channel.id,
+			"message": &message_id,
+			"player": &uuid,
+			"emoji": emoji
+		}))
+		.unwrap();
+		broadcast_from(&database, &socket_sender, &channel, &uuid, &message).await;
+	}
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Emoji are not checked against any list, as clients may support different sets, but they have to be short and
+/// printable
+fn valid_emoji(emoji: &str) -> bool {
+	!emoji.is_empty() && emoji.len() <= 64 && !emoji.chars().any(|char| char.is_whitespace() || char.is_control())
+}
+
+/// The number of messages returned by [`get_messages`] if no limit is given
+const DEFAULT_MESSAGE_LIMIT: u32 = 50;
+const MAX_MESSAGE_LIMIT: u32 = 100;
+
+/// Get messages from a channel, ordered from oldest to newest. By default the latest messages are returned, at most one
+/// of `before`, `after` or `around` may be given to paginate.
+#[utoipa::path(
+	get,
+	path = "/channel/{id}/messages",
+	tag = "channel",
+	params(("id" = u64, Path), MessageQuery),
+	security(("access_token" = [])),
+	responses(
+		(status = OK, body = Vec<Message>, headers(
+			("Has-More" = bool, description = "Whether there are more messages in the direction of pagination, in either direction with `around`"),
+			("Has-More-Before" = bool, description = "Only with `around`, whether there are older messages"),
+			("Has-More-After" = bool, description = "Only with `around`, whether there are newer messages"),
+		)),
+		(status = BAD_REQUEST, description = "The channel does not exist, the authenticated user does not participate in it, or the query is invalid"),
+	)
+)]
+pub async fn get_messages(
+	State(ApiState { database, .. }): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(channel_id): Path<Id>,
+	Query(MessageQuery {
+		before,
+		after,
+		around,
+		limit,
+	}): Query<MessageQuery>,
+) -> Result<(AppendHeaders<Vec<(&'static str, String)>>, Json<Vec<Message>>), ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+
+	let limit = limit.unwrap_or(DEFAULT_MESSAGE_LIMIT);
+	if !(1..=MAX_MESSAGE_LIMIT).contains(&limit) {
+		Err(StatusCode::BAD_REQUEST)?
+	}
+
+	let (mut messages, headers) = match (before, after, around) {
+		(before, None, None) => {
+			let (messages, has_more) =
+				get_message_page(&database, &channel, before.map(|Cursor(id)| *id), None, limit).await?;
+			(messages, vec![("Has-More", has_more.to_string())])
+		}
+		(None, Some(Cursor(after)), None) => {
+			let (messages, has_more) = get_message_page(&database, &channel, None, Some(*after), limit).await?;
+			(messages, vec![("Has-More", has_more.to_string())])
+		}
+		(None, None, Some(Cursor(around))) => {
+			// The message itself counts towards the older half
+			let (mut older, older_more) =
+				get_message_page(&database, &channel, Some(around.saturating_add(1)), None, limit.div_ceil(2)).await?;
+			let (newer, newer_more) = get_message_page(&database, &channel, None, Some(*around), limit / 2).await?;
+			older.extend(newer);
+			let headers = vec![
+				("Has-More", (older_more || newer_more).to_string()),
+				("Has-More-Before", older_more.to_string()),
+				("Has-More-After", newer_more.to_string()),
+			];
+			(older, headers)
+		}
+		_ => Err(StatusCode::BAD_REQUEST)?,
+	};
+
+	annotate_messages(&database, &uuid, &mut messages).await?;
+
+	Ok((AppendHeaders(headers), Json(messages)))
+}
+
+/// Fills in the parts of messages that depend on who requests them, their reactions and whether the sender is blocked
+async fn annotate_messages(database: &PgPool, uuid: &Uuid, messages: &mut [Message]) -> Result<(), sqlx::Error> {
+	let blocked = user::blocked(database, uuid).await?;
+	for message in messages.iter_mut() {
+		message.blocked = blocked.contains(&message.sender);
+	}
+
+	let message_ids: Vec<i64> = messages.iter().map(|m| m.id as i64).collect();
+	let reactions = query!(
+		r#"SELECT message, emoji, COUNT(*) AS "count!", bool_or(player = $2) AS "me!"
+			FROM message_reactions WHERE message = ANY($1)
+			GROUP BY message, emoji ORDER BY min(created)"#,
+		&message_ids,
+		uuid
+	)
+	.fetch_all(database)
+	.await?;
+	for reaction in reactions {
+		if let Some(message) = messages.iter_mut().find(|m| m.id == reaction.message as u64) {
+			message.reactions.push(Reaction {
+				emoji: reaction.emoji,
+				count: reaction.count as u32,
+				me: reaction.me,
+			});
+		}
+	}
+	Ok(())
+}
+
+/// Queries [`MessageRecord`]s, the given SQL continues after the selected columns and has to provide `messages`, the
+/// message replied to as `replied` and `message_pins`
+macro_rules! query_messages {
+	($from:literal, $($args:tt)*) => {
+		query_as!(
+			MessageRecord,
+			r#"SELECT messages.id,
+			messages.channel_id,
+			messages.sender,
+			messages.sender_name,
+			messages.content,
+			messages.send_time,
+			messages.edited_at,
+			messages.reply_to,
+			messages.mentions,
+			ARRAY(SELECT image FROM message_attachments WHERE message = messages.id ORDER BY position) AS "attachments!",
+			message_pins.message IS NOT NULL AS "pinned!",
+			replied.sender AS "reply_sender?",
+			replied.sender_name AS "reply_sender_name?",
+			replied.content AS "reply_content?"
+			"# + $from,
+			$($args)*
+		)
+	};
+}
+
+/// Gets up to `limit` messages older than `before`, or if `after` is given, newer than it, ordered from oldest to newest.
+/// Also returns whether there are more messages in that direction.
+async fn get_message_page(
+	database: &PgPool,
+	channel: &Channel,
+	before: Option<u64>,
+	after: Option<u64>,
+	limit: u32,
+) -> Result<(Vec<Message>, bool), ApiError> {
+	let records = query_messages!(
+		r#"FROM messages
+			LEFT JOIN messages AS replied ON replied.id = messages.reply_to
+			LEFT JOIN message_pins ON message_pins.message = messages.id
+			WHERE messages.channel_id = $1
+				AND ($2::BIGINT IS NULL OR messages.id < $2)
+				AND ($3::BIGINT IS NULL OR messages.id > $3)
+			ORDER BY
+				CASE WHEN $3::BIGINT IS NULL THEN messages.id END DESC,
+				CASE WHEN $3::BIGINT IS NOT NULL THEN messages.id END ASC
+			LIMIT $4"#,
+		&channel.id as _,
+		// Ids past the range of stored ids are newer than all messages
+		before.map(|id| i64::try_from(id).unwrap_or(i64::MAX)),
+		after.map(|id| i64::try_from(id).unwrap_or(i64::MAX)),
+		// One more than requested to find out whether there are more
+		limit as i64 + 1
+	)
+	.fetch_all(database)
+	.await?;
+
+	let has_more = records.len() > limit as usize;
+	let mut messages: Vec<Message> = records.into_iter().take(limit as usize).map(Message::from).collect();
+	messages.sort_by_key(|m| m.id);
+
+	Ok((messages, has_more))
+}
+
+/// A row of the `messages` table joined with the message replied to, see `query_messages!`
+struct MessageRecord {
+	id: i64,
+	channel_id: i64,
+	sender: Uuid,
+	sender_name: String,
+	content: String,
+	send_time: NaiveDateTime,
+	edited_at: Option<NaiveDateTime>,
+	reply_to: Option<i64>,
+	mentions: Vec<Uuid>,
+	attachments: Vec<i64>,
+	pinned: bool,
+	reply_sender: Option<Uuid>,
+	reply_sender_name: Option<String>,
+	reply_content: Option<String>,
+}
+
+impl From<MessageRecord> for Message {
+	fn from(m: MessageRecord) -> Self {
+		Message {
+			id: m.id as u64,
+			channel_id: m.channel_id as u64,
+			sender: m.sender,
+			sender_name: m.sender_name,
+			content: m.content,
+			timestamp: m.send_time.and_utc(),
+			edited_at: m.edited_at.map(|edited_at| edited_at.and_utc()),
+			reply_to: m.reply_to.map(|id| Reply {
+				id: id as u64,
+				sender: m.reply_sender,
+				sender_name: m.reply_sender_name,
+				content: m.reply_content,
+			}),
+			reactions: Vec::new(),
+			mentions: m.mentions,
+			attachments: m.attachments.into_iter().map(|id| id as u64).collect(),
+			pinned: m.pinned,
+			blocked: false,
+		}
+	}
+}
+
+/// Search the messages of all channels the authenticated user participates in, except direct message channels,
+/// ordered from newest to oldest
+#[utoipa::path(
+	get,
+	path = "/channels/search",
+	tag = "channel",
+	params(SearchQuery),
+	security(("access_token" = [])),
+	responses(
+		(status = OK, body = Vec<Message>, headers(("Has-More" = bool, description = "Whether there are older results"))),
+		(status = BAD_REQUEST, description = "The query is empty, or the limit is invalid"),
+	)
+)]
+pub async fn search(
+	State(ApiState { database, .. }): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Query(SearchQuery {
+		q,
+		channel,
+		sender,
+		before,
+		after,
+		limit,
+	}): Query<SearchQuery>,
+) -> Result<([(&'static str, String); 1], Json<Vec<Message>>), ApiError> {
+	let limit = limit.unwrap_or(DEFAULT_MESSAGE_LIMIT);
+	if q.trim().is_empty() || !(1..=MAX_MESSAGE_LIMIT).contains(&limit) {
+		Err(StatusCode::BAD_REQUEST)?
+	}
+
+	let records = query_messages!(
+		r#"FROM messages
+			JOIN (
+				SELECT id FROM channels WHERE owner = $1
+				UNION SELECT channel_id FROM channel_members WHERE player = $1
+				EXCEPT SELECT channel_id FROM direct_messages
+			) AS ids ON ids.id = messages.channel_id
+			LEFT JOIN messages AS replied ON replied.id = messages.reply_to
+			LEFT JOIN message_pins ON message_pins.message = messages.id
+			WHERE to_tsvector('simple', messages.content) @@ websearch_to_tsquery('simple', $2)
+				AND ($3::BIGINT IS NULL OR messages.channel_id = $3)
+				AND ($4::UUID IS NULL OR messages.sender = $4)
+				AND ($5::BIGINT IS NULL OR messages.id < $5)
+				AND ($6::BIGINT IS NULL OR messages.id > $6)
+			ORDER BY messages.id DESC
+			LIMIT $7"#,
+		uuid,
+		q,
+		channel as _,
+		sender,
+		// Ids past the range of stored ids are newer than all messages
+		before.map(|Cursor(id)| i64::try_from(*id).unwrap_or(i64::MAX)),
+		after.map(|Cursor(id)| i64::try_from(*id).unwrap_or(i64::MAX)),
+		// One more than requested to find out whether there are more
+		limit as i64 + 1
+	)
+	.fetch_all(&database)
+	.await?;
+
+	let has_more = records.len() > limit as usize;
+	let mut messages: Vec<Message> = records.into_iter().take(limit as usize).map(Message::from).collect();
+
+	annotate_messages(&database, &uuid, &mut messages).await?;
+
+	Ok(([("Has-More", has_more.to_string())], Json(messages)))
+}
+
+/// The maximum number of pinned messages per channel
+const MAX_PINS_PER_CHANNEL: i64 = 50;
+
+/// Get the pinned messages of a channel, the most recently pinned first
+#[utoipa::path(
+	get,
+	path = "/channel/{id}/pins",
+	tag = "channel",
+	params(("id" = u64, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = OK, body = Vec<Message>),
+		(status = BAD_REQUEST, description = "The channel does not exist or the authenticated user does not participate in it"),
+	)
+)]
+pub async fn get_pins(
+	State(ApiState { database, .. }): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(channel_id): Path<Id>,
+) -> Result<Json<Vec<Message>>, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+
+	let mut messages: Vec<Message> = query_messages!(
+		r#"FROM message_pins
+			JOIN messages ON messages.id = message_pins.message
+			LEFT JOIN messages AS replied ON replied.id = messages.reply_to
+			WHERE message_pins.channel_id = $1
+			ORDER BY message_pins.pinned DESC, messages.id DESC"#,
+		&channel.id as _
+	)
+	.fetch_all(&database)
+	.await?
+	.into_iter()
+	.map(Message::from)
+	.collect();
+
+	annotate_messages(&database, &uuid, &mut messages).await?;
+
+	Ok(Json(messages))
+}
+
+/// Pin a message, pinning a message twice has no effect. Pinned messages are not deleted by the persistence of the
+/// channel.
+#[utoipa::path(
+	put,
+	path = "/channel/{id}/pins/{message_id}",
+	tag = "channel",
+	params(("id" = u64, Path), ("message_id" = u64, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist, the authenticated user does not participate in it, or the channel already has the maximum number of pinned messages"),
+		(status = FORBIDDEN, description = "The authenticated user is neither the owner nor a moderator of the channel"),
+		(status = NOT_FOUND, description = "The message does not exist in this channel"),
+	)
+)]
+pub async fn put_pin(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path((channel_id, message_id)): Path<(Id, Id)>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	channel.require(&uuid, Permission::PinMessages)?;
+	get_message_sender(&database, &channel, &message_id).await?;
+
+	let mut transaction = database.begin().await?;
+
+	lock_channel(&mut transaction, &channel.id).await?;
+	let pins =
+		query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM message_pins WHERE channel_id = $1"#, &channel.id as _)
+			.fetch_one(&mut *transaction)
+			.await?;
+	if pins >= MAX_PINS_PER_CHANNEL {
+		let pinned = query!("SELECT 1 AS pinned FROM message_pins WHERE message = $1", &message_id as _)
+			.fetch_optional(&mut *transaction)
+			.await?;
+		if pinned.is_none() {
+			Err(StatusCode::BAD_REQUEST)?
+		}
+	}
+
+	let inserted = query!(
+		"INSERT INTO message_pins (message, channel_id, pinned_by) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+		&message_id as _,
+		&channel.id as _,
+		uuid
+	)
+	.execute(&mut *transaction)
+	.await?
+	.rows_affected();
+
+	transaction.commit().await?;
+
+	if inserted > 0 {
+		let message = serde_json::to_string(&json!({
+			"target": "pins_update",
+			"channel": &channel.id,
+			"message": &message_id,
+			"player": &uuid,
+			"pinned": true
+		}))
+		.unwrap();
+		broadcast(&socket_sender, &channel, &uuid, &message);
+	}
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Unpin a message, it is subject to the persistence of the channel again
+#[utoipa::path(
+	delete,
+	path = "/channel/{id}/pins/{message_id}",
+	tag = "channel",
+	params(("id" = u64, Path), ("message_id" = u64, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist or the authenticated user does not participate in it"),
+		(status = FORBIDDEN, description = "The authenticated user is neither the owner nor a moderator of the channel"),
+		(status = NOT_FOUND, description = "The message does not exist in this channel"),
+	)
+)]
+pub async fn delete_pin(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path((channel_id, message_id)): Path<(Id, Id)>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	channel.require(&uuid, Permission::PinMessages)?;
+	get_message_sender(&database, &channel, &message_id).await?;
+
+	let deleted = query!("DELETE FROM message_pins WHERE message = $1", &message_id as _)
+		.execute(&database)
+		.await?
+		.rows_affected();
+
+	if deleted > 0 {
+		let message = serde_json::to_string(&json!({
+			"target": "pins_update",
+			"channel": &channel.id,
+			"message": &message_id,
+			"player": &uuid,
+			"pinned": false
+		}))
+		.unwrap();
+		broadcast(&socket_sender, &channel, &uuid, &message);
+	}
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Mark the messages of a channel as read up to and including the given message, or the latest one. The read marker
+/// may also be moved back to mark messages as unread again.
+#[utoipa::path(
+	post,
+	path = "/channel/{id}/ack",
+	tag = "channel",
+	params(("id" = u64, Path), AckQuery),
+	security(("access_token" = [])),
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist or the authenticated user does not participate in it"),
+		(status = NOT_FOUND, description = "The message does not exist in this channel"),
+	)
+)]
+pub async fn post_ack(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(channel_id): Path<Id>,
+	Query(AckQuery { message }): Query<AckQuery>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+
+	let message = match message {
+		Some(message) => {
+			get_message_sender(&database, &channel, &message).await?;
+			message
+		}
+		None => match query!("SELECT max(id) FROM messages WHERE channel_id = $1", &channel.id as _)
+			.fetch_one(&database)
+			.await?
+			.max
+		{
+			Some(latest) => Id::from(latest as u64),
+			// Nothing to read yet
+			None => return Ok(StatusCode::NO_CONTENT),
+		},
+	};
+
+	query!(
+		r#"INSERT INTO channel_read_markers (channel_id, player, message) VALUES ($1, $2, $3)
+			ON CONFLICT (channel_id, player) DO UPDATE SET message = $3"#,
+		&channel.id as _,
+		uuid,
+		&message as _
+	)
+	.execute(&database)
+	.await?;
+
+	// The gateway connection may belong to another client of the same user, which needs to update its read state
+	if let Some(socket) = socket_sender.get(&uuid) {
+		let _ = socket.send(
+			serde_json::to_string(&json!({
+				"target": "channel_ack",
+				"channel": &channel.id,
+				"message": &message
+			}))
+			.unwrap(),
+		);
+	}
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove (kick) a user from a channel
+#[utoipa::path(
+	post,
+	path = "/channel/{id}/remove",
+	tag = "channel",
+	params(("id" = u64, Path), RemoveUser),
+	security(("access_token" = [])),
+	responses(
+		(status = OK),
+		(status = BAD_REQUEST, description = "The channel does not exist, or either user does not participate in it"),
+		(status = FORBIDDEN, description = "The authenticated user may not kick the user, moderators can only kick members and read-only users"),
+	)
+)]
+pub async fn remove_user(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(channel_id): Path<Id>,
+	Query(RemoveUser { user }): Query<RemoveUser>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	channel.check_kick(&uuid, &user)?;
+
+	let mut transaction = database.begin().await?;
+	lock_channel(&mut transaction, &channel.id).await?;
+	remove_participant(&mut *transaction, &channel.id, &user).await?;
+	transaction.commit().await?;
+	announce_leave(&database, &socket_sender, &channel.id, &channel.channel_data.owner, &user, Some(&uuid)).await;
+
+	Ok(StatusCode::OK)
+}
+
+/// Make a participant the owner of the channel, the authenticated user stays in the channel as a member
+#[utoipa::path(
+	put,
+	path = "/channel/{id}/owner",
+	tag = "channel",
+	params(("id" = u64, Path)),
+	security(("access_token" = [])),
+	request_body = PutOwner,
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist, or either user does not participate in it"),
+		(status = FORBIDDEN, description = "The authenticated user does not own the channel"),
+	)
+)]
+pub async fn put_owner(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(channel_id): Path<Id>,
+	Json(PutOwner { owner }): Json<PutOwner>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	if channel.role(&uuid) != Role::Owner {
+		Err(StatusCode::FORBIDDEN)?
+	}
+	if !channel.channel_data.participants.contains(&owner) {
+		Err(StatusCode::BAD_REQUEST)?
+	}
+
+	let mut transaction = database.begin().await?;
+	lock_channel(&mut transaction, &channel.id).await?;
+	transfer_ownership(&mut transaction, &channel.id, &uuid, &owner, true).await?;
+	transaction.commit().await?;
+	announce_owner_change(&database, &socket_sender, channel.id, &owner, &uuid).await;
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// The participant who has been in the channel the longest
+async fn successor<'e>(executor: impl PgExecutor<'e>, channel_id: &Id) -> Result<Option<Uuid>, sqlx::Error> {
+	Ok(query!(
+		"SELECT player FROM channel_members WHERE channel_id = $1 ORDER BY joined_at, player LIMIT 1",
+		channel_id as _
+	)
+	.fetch_optional(executor)
+	.await?
+	.map(|rec| rec.player))
 }
 
-#[derive(Deserialize)]
-pub struct QueryBefore {
-	before: Option<DateTime<Utc>>,
+/// Makes a participant the owner of a channel, the previous owner becomes a member if they `stay`
+async fn transfer_ownership(
+	connection: &mut PgConnection,
+	channel_id: &Id,
+	previous_owner: &Uuid,
+	owner: &Uuid,
+	stay: bool,
+) -> Result<(), sqlx::Error> {
+	query!("UPDATE channels SET owner = $2, last_updated = LOCALTIMESTAMP WHERE id = $1", channel_id as _, owner)
+		.execute(&mut *connection)
+		.await?;
+	remove_participant(&mut *connection, channel_id, owner).await?;
+	if stay {
+		add_participant(&mut *connection, channel_id, previous_owner).await?;
+	}
+	Ok(())
+}
+
+/// Passes every channel owned by a user on to their longest-standing participant, so that they are not deleted along
+/// with the user's account. Channels without participants are left to be deleted. Returns the channels passed on and
+/// their new owners.
+pub async fn pass_on_channels(connection: &mut PgConnection, owner: &Uuid) -> Result<Vec<(Id, Uuid)>, sqlx::Error> {
+	// Direct message channels are deleted along with either user
+	let owned = query!(
+		"SELECT id FROM channels WHERE owner = $1 AND id NOT IN (SELECT channel_id FROM direct_messages)",
+		owner
+	)
+	.fetch_all(&mut *connection)
+	.await?;
+
+	let mut passed_on = Vec::new();
+	for channel in owned {
+		let channel_id = Id::from(channel.id as u64);
+		lock_channel(connection, &channel_id).await?;
+		if let Some(successor) = successor(&mut *connection, &channel_id).await? {
+			transfer_ownership(connection, &channel_id, owner, &successor, false).await?;
+			passed_on.push((channel_id, successor));
+		}
+	}
+	Ok(passed_on)
+}
+
+/// Sends a `channel_owner_changed` event to the new owner and the participants of a channel
+pub async fn announce_owner_change(
+	database: &PgPool,
+	socket_sender: &DashMap<Uuid, UnboundedSender<String>>,
+	channel_id: Id,
+	owner: &Uuid,
+	previous_owner: &Uuid,
+) {
+	// Called after the change is committed, so failing to announce it must not fail the request
+	let id = *channel_id;
+	let Ok(channel) = get_channel(database, owner, channel_id).await else {
+		warn!("Failed to announce the owner change of channel {id}");
+		return;
+	};
+	let message = serde_json::to_string(&json!({
+		"target": "channel_owner_changed",
+		"channel": &channel.id,
+		"owner": owner,
+		"previous_owner": previous_owner
+	}))
+	.unwrap();
+	broadcast(socket_sender, &channel, previous_owner, &message);
+}
+
+/// Tells everyone in a channel about users who joined it, except the user who added them
+pub async fn announce_joins(
+	database: &PgPool,
+	socket_sender: &DashMap<Uuid, UnboundedSender<String>>,
+	channel_id: &Id,
+	players: &[Uuid],
+	added_by: &Uuid,
+) {
+	let Some(player) = players.first() else {
+		return;
+	};
+	let Ok(channel) = get_channel(database, player, Id::from(**channel_id)).await else {
+		warn!("Failed to announce joins to channel {}", **channel_id);
+		return;
+	};
+	for player in players {
+		let message = serde_json::to_string(&json!({
+			"target": "channel_member_join",
+			"channel": &channel.id,
+			"player": player
+		}))
+		.unwrap();
+		broadcast(socket_sender, &channel, added_by, &message);
+	}
+}
+
+/// Tells the remaining participants of a channel that a user left, or was removed by another user. A removed user is
+/// also told that they were.
+pub async fn announce_leave(
+	database: &PgPool,
+	socket_sender: &DashMap<Uuid, UnboundedSender<String>>,
+	channel_id: &Id,
+	owner: &Uuid,
+	player: &Uuid,
+	removed_by: Option<&Uuid>,
+) {
+	let Ok(channel) = get_channel(database, owner, Id::from(**channel_id)).await else {
+		warn!("Failed to announce that {player} left channel {}", **channel_id);
+		return;
+	};
+	let message = serde_json::to_string(&json!({
+		"target": "channel_member_leave",
+		"channel": &channel.id,
+		"player": player,
+		"removed_by": removed_by
+	}))
+	.unwrap();
+	broadcast(socket_sender, &channel, removed_by.unwrap_or(player), &message);
+
+	if let Some(removed_by) = removed_by
+		&& let Some(socket) = socket_sender.get(player)
+	{
+		let _ = socket.send(
+			serde_json::to_string(&json!({
+				"target": "channel_removed",
+				"channel": &channel.id,
+				"removed_by": removed_by
+			}))
+			.unwrap(),
+		);
+	}
+}
+
+/// Change the role of a participant, only the owner can do this
+#[utoipa::path(
+	put,
+	path = "/channel/{id}/roles/{user}",
+	tag = "channel",
+	params(("id" = u64, Path), ("user" = Uuid, Path)),
+	security(("access_token" = [])),
+	request_body = PutRole,
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist, either user does not participate in it, or the role is `owner`"),
+		(status = FORBIDDEN, description = "The authenticated user does not own the channel"),
+	)
+)]
+pub async fn put_role(
+	State(ApiState {
+		database,
+		socket_sender,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path((channel_id, user)): Path<(Id, Uuid)>,
+	Json(PutRole { role }): Json<PutRole>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	channel.require(&uuid, Permission::ManageRoles)?;
+	if !channel.channel_data.participants.contains(&user) || role == Role::Owner {
+		Err(StatusCode::BAD_REQUEST)?
+	}
+
+	query!(
+		"UPDATE channel_members SET role = $3 WHERE channel_id = $1 AND player = $2",
+		&channel.id as _,
+		user,
+		role as _
+	)
+	.execute(&database)
+	.await?;
+
+	let message = serde_json::to_string(&json!({
+		"target": "channel_role_update",
+		"channel": &channel.id,
+		"player": &user,
+		"role": role
+	}))
+	.unwrap();
+	broadcast(&socket_sender, &channel, &uuid, &message);
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Gets the direct message channel between two users, creating it if it does not exist yet
+pub async fn get_or_create_dm(database: &PgPool, uuid: &Uuid, other: &Uuid) -> Result<Id, ApiError> {
+	let (player_a, player_b) = if uuid < other { (uuid, other) } else { (other, uuid) };
+
+	let mut transaction = database.begin().await?;
+	let id = Id::new();
+	query!(
+		"INSERT INTO channels (id, name, owner, persistence) VALUES ($1, '', $2, $3)",
+		&id as _,
+		player_a,
+		Persistence::Channel.id() as i16
+	)
+	.execute(&mut *transaction)
+	.await?;
+	// Waits for a concurrent transaction creating the same channel
+	let created = query!(
+		"INSERT INTO direct_messages (channel_id, player_a, player_b) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+		&id as _,
+		player_a,
+		player_b
+	)
+	.execute(&mut *transaction)
+	.await?
+	.rows_affected();
+
+	if created == 0 {
+		transaction.rollback().await?;
+		let existing =
+			query!("SELECT channel_id FROM direct_messages WHERE player_a = $1 AND player_b = $2", player_a, player_b)
+				.fetch_one(database)
+				.await?;
+		return Ok(Id::from(existing.channel_id as u64));
+	}
+
+	add_participant(&mut *transaction, &id, player_b).await?;
+	transaction.commit().await?;
+	Ok(id)
+}
+
+/// The length of generated invite codes
+const INVITE_CODE_LENGTH: usize = 8;
+
+/// Create an invite code, which anyone can use to join the channel
+#[utoipa::path(
+	post,
+	path = "/channel/{id}/invites",
+	tag = "channel",
+	params(("id" = u64, Path)),
+	security(("access_token" = [])),
+	request_body = CreateInvite,
+	responses(
+		(status = OK, body = InviteCode),
+		(status = BAD_REQUEST, description = "The channel does not exist, the authenticated user does not participate in it, or the body is invalid"),
+		(status = FORBIDDEN, description = "The role of the authenticated user does not allow inviting"),
+	)
+)]
+pub async fn post_invite_code(
+	State(ApiState { database, .. }): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(channel_id): Path<Id>,
+	Json(create_invite): Json<CreateInvite>,
+) -> Result<Json<InviteCode>, ApiError> {
+	create_invite.validate()?;
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	channel.require(&uuid, Permission::Invite)?;
+
+	// Collisions are unlikely, but cheap to retry
+	loop {
+		let code: String = rand::rng()
+			.sample_iter(Alphanumeric)
+			.take(INVITE_CODE_LENGTH)
+			.map(char::from)
+			.collect();
+		let invite = query!(
+			r#"INSERT INTO channel_invite_codes (code, channel_id, creator, expires, max_uses)
+				VALUES ($1, $2, $3, LOCALTIMESTAMP + make_interval(secs => $4), $5)
+				ON CONFLICT DO NOTHING
+				RETURNING created, expires"#,
+			code,
+			&channel.id as _,
+			uuid,
+			create_invite.max_age.map(f64::from),
+			create_invite.max_uses.map(|uses| uses as i32)
+		)
+		.fetch_optional(&database)
+		.await?;
+
+		if let Some(invite) = invite {
+			return Ok(Json(InviteCode {
+				code,
+				channel: *channel.id,
+				creator: uuid,
+				created: invite.created.and_utc(),
+				expires: invite.expires.map(|expires| expires.and_utc()),
+				max_uses: create_invite.max_uses,
+				uses: 0,
+			}));
+		}
+	}
+}
+
+/// Get the invite codes of a channel which can still be used
+#[utoipa::path(
+	get,
+	path = "/channel/{id}/invites",
+	tag = "channel",
+	params(("id" = u64, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = OK, body = Vec<InviteCode>),
+		(status = BAD_REQUEST, description = "The channel does not exist or the authenticated user does not participate in it"),
+		(status = FORBIDDEN, description = "The role of the authenticated user does not allow inviting"),
+	)
+)]
+pub async fn get_invite_codes(
+	State(ApiState { database, .. }): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(channel_id): Path<Id>,
+) -> Result<Json<Vec<InviteCode>>, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	channel.require(&uuid, Permission::Invite)?;
+
+	Ok(Json(
+		query!(
+			r#"SELECT code, creator, created, expires, max_uses, uses FROM channel_invite_codes
+				WHERE channel_id = $1
+					AND (expires IS NULL OR expires > LOCALTIMESTAMP)
+					AND (max_uses IS NULL OR uses < max_uses)
+				ORDER BY created"#,
+			&channel.id as _
+		)
+		.fetch_all(&database)
+		.await?
+		.into_iter()
+		.map(|invite| InviteCode {
+			code: invite.code,
+			channel: *channel.id,
+			creator: invite.creator,
+			created: invite.created.and_utc(),
+			expires: invite.expires.map(|expires| expires.and_utc()),
+			max_uses: invite.max_uses.map(|uses| uses as u32),
+			uses: invite.uses as u32,
+		})
+		.collect(),
+	))
+}
+
+/// Revoke an invite code
+#[utoipa::path(
+	delete,
+	path = "/channel/{id}/invites/{code}",
+	tag = "channel",
+	params(("id" = u64, Path), ("code" = String, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The channel does not exist or the authenticated user does not participate in it"),
+		(status = FORBIDDEN, description = "The role of the authenticated user does not allow inviting"),
+		(status = NOT_FOUND, description = "The invite code does not exist for this channel"),
+	)
+)]
+pub async fn delete_invite_code(
+	State(ApiState { database, .. }): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path((channel_id, code)): Path<(Id, String)>,
+) -> Result<StatusCode, ApiError> {
+	let channel = get_channel(&database, &uuid, channel_id).await?;
+	channel.require(&uuid, Permission::Invite)?;
+
+	let deleted =
+		query!("DELETE FROM channel_invite_codes WHERE code = $1 AND channel_id = $2", code, &channel.id as _)
+			.execute(&database)
+			.await?
+			.rows_affected();
+	if deleted == 0 {
+		Err(StatusCode::NOT_FOUND)?
+	}
+
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Preview the channel an invite code leads to
+#[utoipa::path(
+	get,
+	path = "/invite/{code}",
+	tag = "channel",
+	params(("code" = String, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = OK, body = InvitePreview),
+		(status = NOT_FOUND, description = "The invite code does not exist, has expired or has been used up"),
+	)
+)]
+pub async fn get_invite(
+	State(ApiState { database, .. }): State<ApiState>,
+	Authentication(_): Authentication,
+	Path(code): Path<String>,
+) -> Result<Json<InvitePreview>, ApiError> {
+	let invite = query!(
+		r#"SELECT channels.id, channels.name, channels.owner,
+				(SELECT COUNT(*) FROM channel_members WHERE channel_members.channel_id = channels.id) AS "participant_count!"
+			FROM channel_invite_codes JOIN channels ON channels.id = channel_invite_codes.channel_id
+			WHERE code = $1
+				AND (expires IS NULL OR expires > LOCALTIMESTAMP)
+				AND (max_uses IS NULL OR uses < max_uses)"#,
+		code
+	)
+	.fetch_optional(&database)
+	.await?
+	.ok_or(StatusCode::NOT_FOUND)?;
+
+	Ok(Json(InvitePreview {
+		code,
+		channel: invite.id as u64,
+		name: invite.name,
+		owner: invite.owner,
+		participant_count: invite.participant_count as u32,
+	}))
+}
+
+/// Join the channel an invite code leads to, using an invite code for a channel the authenticated user already
+/// participates in has no effect
+#[utoipa::path(
+	post,
+	path = "/invite/{code}",
+	tag = "channel",
+	params(("code" = String, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = OK, body = String, content_type = "text/plain", description = "The id of the channel"),
+		(status = NOT_FOUND, description = "The invite code does not exist, has expired or has been used up"),
+		(status = FORBIDDEN, description = "The authenticated user and the creator of the invite code or the owner of the channel have blocked one another, or the channel already has the maximum number of participants"),
+	)
+)]
+pub async fn post_invite(
+	State(ApiState {
+		database,
+		socket_sender,
+		config,
+		..
+	}): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(code): Path<String>,
+) -> Result<String, ApiError> {
+	let mut transaction = database.begin().await?;
+
+	let invite = query!(
+		r#"UPDATE channel_invite_codes SET uses = uses + 1
+			FROM channels
+			WHERE code = $1 AND channels.id = channel_invite_codes.channel_id
+				AND (expires IS NULL OR expires > LOCALTIMESTAMP)
+				AND (max_uses IS NULL OR uses < max_uses)
+			RETURNING channels.id, channels.name, channels.owner, channel_invite_codes.creator"#,
+		code
+	)
+	.fetch_optional(&mut *transaction)
+	.await?
+	.ok_or(StatusCode::NOT_FOUND)?;
+	let channel_id = Id::from(invite.id as u64);
+
+	let member =
+		query!("SELECT 1 AS member FROM channel_members WHERE channel_id = $1 AND player = $2", &channel_id as _, uuid)
+			.fetch_optional(&mut *transaction)
+			.await?;
+	if invite.owner == uuid || member.is_some() {
+		// Rolling back leaves the use of the invite code uncounted
+		return Ok(channel_id.to_string());
+	}
+	let blocks = user::blocks(&mut *transaction, &uuid).await?;
+	if blocks.contains(&invite.creator) || blocks.contains(&invite.owner) {
+		Err(StatusCode::FORBIDDEN)?
+	}
+
+	lock_channel(&mut transaction, &channel_id).await?;
+	add_participant(&mut *transaction, &channel_id, &uuid).await?;
+	if over_participant_limit(&mut transaction, &channel_id, &config).await? {
+		Err(StatusCode::FORBIDDEN)?
+	}
+	query!("DELETE FROM channel_invites WHERE channel = $1 AND player = $2", &channel_id as _, uuid)
+		.execute(&mut *transaction)
+		.await?;
+	transaction.commit().await?;
+
+	if let Some(socket) = socket_sender.get(&invite.creator) {
+		let _ = socket.send(
+			serde_json::to_string(&json!({
+				"target": "channel_invite_reaction",
+				"channel": &channel_id,
+				"channel_name": invite.name,
+				"player": &uuid,
+				"accepted": true,
+				"code": code
+			}))
+			.unwrap(),
+		);
+	}
+	announce_joins(&database, &socket_sender, &channel_id, &[uuid], &uuid).await;
+
+	Ok(channel_id.to_string())
+}
+
+pub async fn evict_expired_invite_codes(ApiState { database, .. }: &ApiState) -> Result<(), TaskError> {
+	query!("DELETE FROM channel_invite_codes WHERE expires <= LOCALTIMESTAMP OR uses >= max_uses")
+		.execute(database)
+		.await?;
+	Ok(())
+}
+
+pub async fn enforce_persistence(ApiState { database, .. }: &ApiState) -> Result<(), TaskError> {
+	delete_expired_messages(database, None).await?;
+	Ok(())
+}
+
+/// Deletes messages according to the [`Persistence`] of their channel, optionally limited to a single channel.
+async fn delete_expired_messages<'e>(
+	executor: impl PgExecutor<'e>,
+	channel_id: Option<&Id>,
+) -> Result<(), sqlx::Error> {
+	query!(
+		r#"DELETE FROM messages USING (
+				SELECT messages.id,
+					row_number() OVER (PARTITION BY messages.channel_id ORDER BY messages.send_time DESC, messages.id DESC) AS position,
+					LOCALTIMESTAMP - messages.send_time > make_interval(secs => channels.persistence_duration_seconds) AS expired,
+					channels.persistence,
+					channels.persistence_count
+				FROM messages JOIN channels ON channels.id = messages.channel_id
+				WHERE channels.persistence != 0 AND ($1::BIGINT IS NULL OR channels.id = $1)
+					AND NOT EXISTS (SELECT 1 FROM message_pins WHERE message_pins.message = messages.id)
+			) AS ranked
+			WHERE messages.id = ranked.id AND CASE ranked.persistence
+				WHEN 1 THEN ranked.expired
+				WHEN 2 THEN ranked.position > ranked.persistence_count
+				WHEN 3 THEN ranked.position > ranked.persistence_count AND ranked.expired
+				ELSE false
+			END"#,
+		channel_id as _
+	)
+	.execute(executor)
+	.await?;
+	Ok(())
+}
+
+/*pub async fn report_message(State(ApiState { database, .. }): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(message_id): Path<Id>,
+) -> Result<StatusCode, ApiError> {
+	todo!("Store reports somewhere and notify us of them!");
+	//Ok(StatusCode::BAD_REQUEST)
+}*/
+
+#[derive(Serialize, ToSchema)]
+pub struct Message {
+	id: u64,
+	channel_id: u64,
+	sender: Uuid,
+	sender_name: String,
+	content: String,
+	timestamp: DateTime<Utc>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	edited_at: Option<DateTime<Utc>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	reply_to: Option<Reply>,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	reactions: Vec<Reaction>,
+	/// Participants mentioned by `@username` or `@everyone`
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	mentions: Vec<Uuid>,
+	/// Ids of the attached images, see `GET /image/{id}`
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	attachments: Vec<u64>,
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	pinned: bool,
+	/// Whether the authenticated user blocked the sender, clients are expected to hide the content
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	blocked: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Reaction {
+	emoji: String,
+	/// The number of players who reacted with this emoji
+	count: u32,
+	/// Whether the authenticated user reacted with this emoji
+	me: bool,
+}
+
+/// A reference to the message replied to. The message may have been deleted since, in which case only the id is present
+#[derive(Serialize, ToSchema)]
+pub struct Reply {
+	id: u64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	sender: Option<Uuid>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	sender_name: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	content: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MessageQuery {
+	/// Only messages older than this message id, or timestamp
+	#[param(value_type = Option<String>)]
+	before: Option<Cursor>,
+	/// Only messages newer than this message id, or timestamp
+	#[param(value_type = Option<String>)]
+	after: Option<Cursor>,
+	/// Messages around and including this message id, or timestamp
+	#[param(value_type = Option<String>)]
+	around: Option<Cursor>,
+	/// The maximum number of messages, between 1 and 100, 50 by default
+	limit: Option<u32>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+	/// The search terms, supporting `"quoted phrases"`, `or` and `-excluded` words
+	q: String,
+	/// Only messages in this channel
+	#[param(value_type = Option<u64>)]
+	channel: Option<Id>,
+	/// Only messages sent by this user
+	sender: Option<Uuid>,
+	/// Only messages older than this message id, or timestamp
+	#[param(value_type = Option<String>)]
+	before: Option<Cursor>,
+	/// Only messages newer than this message id, or timestamp
+	#[param(value_type = Option<String>)]
+	after: Option<Cursor>,
+	/// The maximum number of messages, between 1 and 100, 50 by default
+	limit: Option<u32>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AckQuery {
+	/// The message to mark as read, the latest message by default
+	#[param(value_type = Option<u64>)]
+	message: Option<Id>,
 }
 
+/// A message id or a timestamp, which is treated like the id of a message sent at that time
 #[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cursor(Id);
+
+impl TryFrom<String> for Cursor {
+	type Error = chrono::ParseError;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		match value.parse::<u64>() {
+			Ok(id) => Ok(Cursor(Id::from(id))),
+			Err(_) => Ok(Cursor(Id::from_timestamp(DateTime::parse_from_rfc3339(&value)?.to_utc()))),
+		}
+	}
+}
+
+#[derive(Deserialize, ToSchema)]
 pub struct PostMessage {
+	/// The message, max. 2000 characters, or the `max_message_length` of the channel
 	content: String,
+	/// The name under which to display this message, max. 179 characters. Used for proxying with PluralKit
 	display_name: String,
+	/// A message in the same channel this message replies to
+	#[serde(default)]
+	#[schema(value_type = Option<u64>)]
+	reply_to: Option<Id>,
+	/// Images shared by the sender to attach to this message, at most 10
+	#[serde(default)]
+	#[schema(value_type = Vec<u64>)]
+	attachments: Vec<Id>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteChannel {
+	/// If the authenticated user owns the channel, leave it instead of deleting it
+	#[serde(default)]
+	leave: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PutOwner {
+	/// The participant to become the new owner
+	owner: Uuid,
+}
+
+#[derive(Deserialize, Validate, ToSchema)]
+pub struct CreateInvite {
+	/// Seconds after which the invite code expires, it does not expire by default
+	#[garde(inner(range(min = 1)))]
+	#[serde(default)]
+	max_age: Option<u32>,
+	/// How often the invite code can be used, without limit by default
+	#[garde(inner(range(min = 1)))]
+	#[serde(default)]
+	max_uses: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InviteCode {
+	code: String,
+	channel: u64,
+	creator: Uuid,
+	created: DateTime<Utc>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	expires: Option<DateTime<Utc>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	max_uses: Option<u32>,
+	uses: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InvitePreview {
+	code: String,
+	channel: u64,
+	name: String,
+	owner: Uuid,
+	/// The number of participants, not including the owner
+	participant_count: u32,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RemoveUser {
+	/// The user to remove
+	user: Uuid,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PutRole {
+	role: Role,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct EditMessage {
+	/// The new message, max. 2000 characters, or the `max_message_length` of the channel
+	content: String,
 }
 
 mod duration {
@@ -535,12 +2553,206 @@ mod duration {
 
 	pub fn deserialize<'d, D: Deserializer<'d>>(deserializer: D) -> Result<Duration, D::Error> {
 		use serde::de::Error;
-		let seconds = u32::deserialize(deserializer)?.min(0) as i64;
-		Duration::try_seconds(seconds).ok_or(Error::custom("invalid duration"))
+		match u32::deserialize(deserializer)? {
+			0 => Err(Error::custom("duration must be at least one second")),
+			seconds => Ok(Duration::seconds(seconds as i64)),
+		}
 	}
 
 	pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
-		let seconds = value.num_seconds().min(0) as u32;
+		let seconds = value.num_seconds().clamp(0, u32::MAX as i64) as u32;
 		serializer.serialize_u32(seconds)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{Persistence, delete_expired_messages};
+	use crate::id::Id;
+	use chrono::Duration;
+	use serde_json::{from_value, json, to_value};
+	use sqlx::{PgPool, query, query_scalar};
+	use uuid::Uuid;
+
+	/// Ages in seconds of the messages seeded by [`seed`], the message at index `i` has the id `i + 1`
+	const MESSAGE_AGES: [i64; 5] = [10, 20, 7200, 7300, 7400];
+
+	fn round_trip(persistence: Persistence) -> Persistence {
+		let stored =
+			Persistence::from(persistence.id() as i16, persistence.count().copied(), persistence.duration().copied())
+				.expect("stored persistence should be readable");
+		from_value(to_value(stored).unwrap()).unwrap()
+	}
+
+	#[test]
+	fn channel() {
+		let persistence: Persistence = from_value(json!({"type": "channel"})).unwrap();
+		assert!(matches!(round_trip(persistence), Persistence::Channel));
+	}
+
+	#[test]
+	fn duration() {
+		let persistence: Persistence = from_value(json!({"type": "duration", "duration": 3600})).unwrap();
+		assert!(matches!(
+			round_trip(persistence),
+			Persistence::Duration { duration } if duration == Duration::hours(1)
+		));
+	}
+
+	#[test]
+	fn count() {
+		let persistence: Persistence = from_value(json!({"type": "count", "count": 50})).unwrap();
+		assert!(matches!(round_trip(persistence), Persistence::Count { count: 50 }));
+	}
+
+	#[test]
+	fn count_and_duration() {
+		let persistence: Persistence =
+			from_value(json!({"type": "count_and_duration", "count": 50, "duration": 86400})).unwrap();
+		assert!(matches!(
+			round_trip(persistence),
+			Persistence::CountAndDuration { count: 50, duration } if duration == Duration::days(1)
+		));
+	}
+
+	#[test]
+	fn zero_duration_is_rejected() {
+		assert!(from_value::<Persistence>(json!({"type": "duration", "duration": 0})).is_err());
+		assert!(from_value::<Persistence>(json!({"type": "count_and_duration", "count": 1, "duration": 0})).is_err());
+	}
+
+	#[test]
+	fn incomplete_settings_are_rejected() {
+		assert!(Persistence::from(1, Some(50), None).is_none());
+		assert!(Persistence::from(2, None, Some(Duration::hours(1))).is_none());
+		assert!(Persistence::from(3, Some(50), None).is_none());
+		assert!(Persistence::from(4, Some(50), Some(Duration::hours(1))).is_none());
+	}
+
+	/// Creates a channel with the given id and persistence holding one message per entry in [`MESSAGE_AGES`], with
+	/// message ids starting at `id * 10 + 1`.
+	async fn seed(database: &PgPool, id: i64, persistence: Persistence) {
+		let owner = Uuid::from_u128(1);
+		query("INSERT INTO players(uuid, username) VALUES ($1, 'owner') ON CONFLICT DO NOTHING")
+			.bind(owner)
+			.execute(database)
+			.await
+			.unwrap();
+		query(
+			"INSERT INTO channels(id, name, owner, persistence, persistence_count, persistence_duration_seconds)
+				VALUES ($1, 'test', $2, $3, $4, $5)",
+		)
+		.bind(id)
+		.bind(owner)
+		.bind(persistence.id() as i16)
+		.bind(persistence.count().map(|count| *count as i32))
+		.bind(persistence.duration().map(Duration::num_seconds))
+		.execute(database)
+		.await
+		.unwrap();
+		for (index, age) in MESSAGE_AGES.iter().enumerate() {
+			query(
+				"INSERT INTO messages(id, channel_id, sender, sender_name, content, send_time)
+					VALUES ($1, $2, $3, 'owner', 'message', LOCALTIMESTAMP - make_interval(secs => $4))",
+			)
+			.bind(id * 10 + index as i64 + 1)
+			.bind(id)
+			.bind(owner)
+			.bind(*age as f64)
+			.execute(database)
+			.await
+			.unwrap();
+		}
+	}
+
+	async fn remaining(database: &PgPool, id: i64) -> Vec<i64> {
+		query_scalar("SELECT id - $1 * 10 FROM messages WHERE channel_id = $1 ORDER BY id")
+			.bind(id)
+			.fetch_all(database)
+			.await
+			.unwrap()
+	}
+
+	#[sqlx::test]
+	async fn channel_keeps_messages(database: PgPool) {
+		seed(&database, 1, Persistence::Channel).await;
+		delete_expired_messages(&database, None).await.unwrap();
+		assert_eq!(remaining(&database, 1).await, [1, 2, 3, 4, 5]);
+	}
+
+	#[sqlx::test]
+	async fn duration_deletes_expired(database: PgPool) {
+		seed(
+			&database,
+			1,
+			Persistence::Duration {
+				duration: Duration::hours(1),
+			},
+		)
+		.await;
+		delete_expired_messages(&database, None).await.unwrap();
+		assert_eq!(remaining(&database, 1).await, [1, 2]);
+	}
+
+	#[sqlx::test]
+	async fn count_keeps_newest(database: PgPool) {
+		seed(&database, 1, Persistence::Count { count: 3 }).await;
+		delete_expired_messages(&database, None).await.unwrap();
+		assert_eq!(remaining(&database, 1).await, [1, 2, 3]);
+	}
+
+	#[sqlx::test]
+	async fn count_and_duration_requires_both(database: PgPool) {
+		seed(
+			&database,
+			1,
+			Persistence::CountAndDuration {
+				count: 4,
+				duration: Duration::hours(1),
+			},
+		)
+		.await;
+		seed(
+			&database,
+			2,
+			Persistence::CountAndDuration {
+				count: 1,
+				duration: Duration::hours(1),
+			},
+		)
+		.await;
+		delete_expired_messages(&database, None).await.unwrap();
+		assert_eq!(remaining(&database, 1).await, [1, 2, 3, 4]);
+		assert_eq!(remaining(&database, 2).await, [1, 2]);
+	}
+
+	#[sqlx::test]
+	async fn pinned_messages_are_kept(database: PgPool) {
+		seed(&database, 1, Persistence::Count { count: 1 }).await;
+		seed(
+			&database,
+			2,
+			Persistence::Duration {
+				duration: Duration::hours(1),
+			},
+		)
+		.await;
+		query("INSERT INTO message_pins(message, channel_id) VALUES (13, 1), (25, 2)")
+			.execute(&database)
+			.await
+			.unwrap();
+		delete_expired_messages(&database, None).await.unwrap();
+		// Pinned messages don't take up one of the counted places either
+		assert_eq!(remaining(&database, 1).await, [1, 3]);
+		assert_eq!(remaining(&database, 2).await, [1, 2, 5]);
+	}
+
+	#[sqlx::test]
+	async fn single_channel(database: PgPool) {
+		seed(&database, 1, Persistence::Count { count: 1 }).await;
+		seed(&database, 2, Persistence::Count { count: 1 }).await;
+		delete_expired_messages(&database, Some(&Id::from(1))).await.unwrap();
+		assert_eq!(remaining(&database, 1).await, [1]);
+		assert_eq!(remaining(&database, 2).await, [1, 2, 3, 4, 5]);
+	}
+}
@@ -1,4 +1,4 @@
-use crate::{ApiState, errors::ApiError};
+use crate::{ApiState, errors::ApiError, errors::TaskError};
 use axum::{
 	Json,
 	extract::{FromRequestParts, State},
@@ -11,7 +11,8 @@ use reqwest::{Client, StatusCode};
 use serde::Serialize;
 use serde_json::Value;
 use sqlx::{PgPool, query};
-use std::{fmt::Write, fs::read_to_string, sync::LazyLock};
+use std::{fmt::Write, fs::read_to_string, mem::take, sync::LazyLock};
+use utoipa::ToSchema;
 
 const PROJECT_ID: &str = "p2rxzX0q";
 
@@ -41,12 +42,15 @@ impl Default for GlobalDataContainer {
 	}
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct GlobalData {
+	/// Total number of players known to the backend
 	total_players: u32,
+	/// Number of currently online players
 	online_players: u32,
 	#[serde(flatten)]
 	modrinth_data: ModrinthData,
+	/// Misc notes, f.e. updates, maintenance notices, ...
 	#[serde(skip_serializing_if = "String::is_empty")]
 	notes: String,
 	#[serde(skip)]
@@ -55,8 +59,9 @@ pub struct GlobalData {
 	pub gateway_user_agents: DashMap<String, u32>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ModrinthData {
+	/// The latest version of the mod, does not include a game version
 	latest_version: String,
 }
 
@@ -71,6 +76,16 @@ impl GlobalData {
 			gateway_user_agents: self.gateway_user_agents.clone(),
 		}
 	}
+
+	/// Carries the user agent counters over from the data being replaced, they are counted continuously instead of
+	/// being fetched with the rest, so this happens under the write lock rather than during the fetch.
+	fn keep_user_agents(self, previous: &mut GlobalData) -> GlobalData {
+		GlobalData {
+			request_user_agents: take(&mut previous.request_user_agents),
+			gateway_user_agents: take(&mut previous.gateway_user_agents),
+			..self
+		}
+	}
 }
 
 impl Clone for GlobalData {
@@ -94,16 +109,15 @@ impl Clone for ModrinthData {
 	}
 }
 
-pub async fn get(
-	State(ApiState {
+#[utoipa::path(get, path = "/global_data", operation_id = "get_global_data", tag = "global", responses((status = OK, body = GlobalData)))]
+pub async fn get(State(state): State<ApiState>) -> Result<Json<GlobalData>, ApiError> {
+	let ApiState {
 		database,
-		cl_args,
 		online_users,
-		client,
 		global_data,
 		..
-	}): State<ApiState>,
-) -> Result<Json<GlobalData>, ApiError> {
+	} = &state;
+
 	let now = Utc::now();
 	let data_container = global_data.read().await;
 	let full_refresh = now.signed_duration_since(data_container.last_full_refresh).num_days() >= 1;
@@ -118,36 +132,68 @@ pub async fn get(
 		return Ok(Json(cloned));
 	}
 	let data = if full_refresh {
-		let request_user_agents = data_container.data.request_user_agents.clone();
-		let gateway_user_agents = data_container.data.gateway_user_agents.clone();
-		GlobalData {
-			total_players: get_total_players(&database).await?,
-			online_players: online_users.len() as u32,
-			modrinth_data: fetch_modrinth_data(client).await?,
-			notes: (cl_args.notes_file.as_ref())
-				.map(|file| read_to_string(file).unwrap_or_else(|_| String::new()))
-				.unwrap_or_else(String::new),
-			request_user_agents,
-			gateway_user_agents,
-		}
+		drop(data_container);
+		fetch_full_data(&state).await?
 	} else {
-		data_container
+		let data = data_container
 			.data
-			.with_players(get_total_players(&database).await?, online_users.len() as u32)
+			.with_players(get_total_players(database).await?, online_users.len() as u32);
+		drop(data_container);
+		data
 	};
-	drop(data_container);
 
 	let mut container = global_data.write().await;
 	if full_refresh {
 		container.last_full_refresh = now;
 	}
 	container.last_player_refresh = now;
-	container.data = data.clone();
+	container.data = data.keep_user_agents(&mut container.data);
+	let data = container.data.clone();
 	drop(container);
 
 	Ok(Json(data))
 }
 
+/// Performs a full refresh ahead of time, so that requests to `/global_data` don't have to wait for Modrinth.
+pub async fn warm_cache(state: &ApiState) -> Result<(), TaskError> {
+	let now = Utc::now();
+	let data = fetch_full_data(state).await?;
+
+	let mut container = state.global_data.write().await;
+	container.last_full_refresh = now;
+	container.last_player_refresh = now;
+	container.data = data.keep_user_agents(&mut container.data);
+	Ok(())
+}
+
+async fn fetch_full_data(
+	ApiState {
+		database,
+		config,
+		online_users,
+		client,
+		..
+	}: &ApiState,
+) -> Result<GlobalData, ApiError> {
+	Ok(GlobalData {
+		total_players: get_total_players(database).await?,
+		online_players: online_users.len() as u32,
+		modrinth_data: fetch_modrinth_data(client.clone(), &config.upstream.modrinth_api).await?,
+		notes: (config.notes_file.as_ref())
+			.map(|file| read_to_string(file).unwrap_or_else(|_| String::new()))
+			.unwrap_or_else(String::new),
+		request_user_agents: DashMap::new(),
+		gateway_user_agents: DashMap::new(),
+	})
+}
+
+/// Prometheus metrics, intended for internal use and not part of the stable API.
+#[utoipa::path(
+	get,
+	path = "/metrics",
+	tag = "global",
+	responses((status = OK, body = String, content_type = "text/plain"))
+)]
 pub async fn metrics(
 	State(ApiState {
 		database,
@@ -158,6 +204,9 @@ pub async fn metrics(
 ) -> Result<String, ApiError> {
 	let lifetime_players = get_total_players(&database).await?;
 	let online_players = online_users.len();
+	let task_runs = query!("SELECT name, running, started, duration_ms, error FROM task_runs ORDER BY name")
+		.fetch_all(&database)
+		.await?;
 
 	let mut response = String::new();
 
@@ -167,7 +216,7 @@ pub async fn metrics(
 		writeln!(response, "# This endpoint is intended for internal use with Prometheus. It is not part of the documented stable API and may be");
 		writeln!(response, "# removed without notice. The `/v1/global_data` endpoint should be preferred, see the following:");
 		writeln!(response, "# https://github.com/AxolotlClient/AxolotlClient-API/blob/main/docs/api_documentation.md#get-global_data");
-		writeln!(response, "");
+		writeln!(response);
 		writeln!(response, "lifetime_players {lifetime_players}");
 		writeln!(response, "online_players {online_players}");
 		let data_container = global_data.read().await;
@@ -184,6 +233,15 @@ pub async fn metrics(
 				writeln!(response, "connections{{mod_version=\"{mod_ver}\", minecraft_version=\"{minecraft_ver}\", mod=\"{note}\"}} {count}");
 			}
 		}
+		for task in task_runs {
+			let name = task.name;
+			writeln!(response, "task_running{{task=\"{name}\"}} {}", task.running as u8);
+			writeln!(response, "task_last_started_seconds{{task=\"{name}\"}} {}", task.started.and_utc().timestamp());
+			if let Some(duration_ms) = task.duration_ms {
+				writeln!(response, "task_last_duration_seconds{{task=\"{name}\"}} {}", duration_ms as f64 / 1000.0);
+				writeln!(response, "task_last_failed{{task=\"{name}\"}} {}", task.error.is_some() as u8);
+			}
+		}
 	};
 
 	Ok(response)
@@ -231,7 +289,7 @@ fn parse_user_agent(agent: String) -> Option<(String, String, String)> {
 			return Some((mod_ver.to_string(), mc_ver.to_string(), mod_name));
 		}
 	}
-	return None;
+	None
 }
 
 async fn get_total_players(database: &PgPool) -> Result<u32, ApiError> {
@@ -241,9 +299,9 @@ async fn get_total_players(database: &PgPool) -> Result<u32, ApiError> {
 		.estimate as u32)
 }
 
-async fn fetch_modrinth_data(client: Client) -> Result<ModrinthData, ApiError> {
+async fn fetch_modrinth_data(client: Client, modrinth_api: &str) -> Result<ModrinthData, ApiError> {
 	let response = client
-		.get("https://api.modrinth.com/v2/project/".to_string() + PROJECT_ID + "/version")
+		.get(modrinth_api.to_string() + "/project/" + PROJECT_ID + "/version")
 		.send()
 		.await?;
 
@@ -1,4 +1,4 @@
-use crate::{ApiState, ClArgs, errors::ApiError, extractors::Authentication};
+use crate::{ApiState, config::Config, errors::ApiError, extractors::Authentication};
 use axum::{Json, body::Body, extract::State, response::IntoResponse, response::Response};
 use chrono::Utc;
 use log::warn;
@@ -13,10 +13,9 @@ use std::{
 	time::Duration,
 };
 use tokio::sync::RwLock;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-const HYPIXEL_API_URL: &str = "https://api.hypixel.net/v2";
-
 pub struct HypixelApiProxyState {
 	cache: Cache<Uuid, Value>,
 	ratelimits: RwLock<Ratelimits>,
@@ -52,7 +51,7 @@ impl HypixelApiProxyState {
 					}
 					size
 				})
-				.time_to_live(Duration::from_secs(1 * 24 * 60 * 60))
+				.time_to_live(Duration::from_secs(24 * 60 * 60))
 				.build(),
 			ratelimits: RwLock::new(Ratelimits {
 				limit: 10,
@@ -63,13 +62,13 @@ impl HypixelApiProxyState {
 	}
 }
 
-#[derive(Deserialize, PartialEq, Eq, Hash)]
+#[derive(Deserialize, PartialEq, Eq, Hash, ToSchema)]
 pub struct RequestDataType {
 	request_type: RequestType,
 	target_player: Uuid,
 }
 
-#[derive(Deserialize, PartialEq, Eq, Hash)]
+#[derive(Deserialize, PartialEq, Eq, Hash, ToSchema)]
 #[serde(rename_all = "snake_case")]
 enum RequestType {
 	NetworkLevel,
@@ -105,7 +104,7 @@ impl PlayerData {
 			bedwars,
 			skywars,
 			duels,
-			rank: rank.clone().to_string(),
+			rank: rank.as_str().to_owned(),
 			rank_formatted: rank
 				.to_string_formatted(player["rankPlusColor"].as_str(), player["monthlyPlusColor"].as_str()),
 			level: network_level,
@@ -119,13 +118,13 @@ impl PlayerData {
 		if rank == "NORMAL" {
 			let mut highest: Rank = Rank::Normal;
 			for current in keys {
-				if let Some(r) = player[current].as_str().map(|s| Rank::of(s)) {
-					if r > highest {
-						highest = r;
-					}
+				if let Some(r) = player[current].as_str().map(Rank::of)
+					&& r > highest
+				{
+					highest = r;
 				}
 			}
-			return highest;
+			highest
 		} else {
 			Rank::of(rank)
 		}
@@ -167,7 +166,7 @@ impl Rank {
 		}
 	}
 
-	fn to_string(&self) -> String {
+	fn as_str(&self) -> &'static str {
 		match self {
 			Rank::Normal => "NORMAL",
 			Rank::Vip => "VIP",
@@ -182,7 +181,6 @@ impl Rank {
 			Rank::JrHelper => "JR_HELPER",
 			Rank::Youtuber => "YOUTUBER",
 		}
-		.to_owned()
 	}
 
 	fn to_string_formatted(&self, plus_color: Option<&str>, superstar_color: Option<&str>) -> String {
@@ -196,12 +194,8 @@ impl Rank {
 				"§b[MVP".to_owned() + plus.get_code() + "+§b]"
 			}
 			Rank::Superstar => {
-				let plus = plus_color
-					.and_then(|s| colors::Code::of(s))
-					.unwrap_or(colors::Code::Red);
-				let color = superstar_color
-					.and_then(|s| colors::Code::of(s))
-					.unwrap_or(colors::Code::Gold);
+				let plus = plus_color.and_then(colors::Code::of).unwrap_or(colors::Code::Red);
+				let color = superstar_color.and_then(colors::Code::of).unwrap_or(colors::Code::Gold);
 				color.get_code().to_owned() + "[MVP" + plus.get_code() + "++" + color.get_code() + "]"
 			}
 			Rank::Admin => "§c[ADMIN]".to_owned(),
@@ -241,25 +235,25 @@ struct BedwarsData {
 impl BedwarsData {
 	fn of(val: &Value) -> BedwarsData {
 		let bedwars_stats = &val["stats"]["Bedwars"];
-		let solo = BedwarsGameData::of("eight_one_", &bedwars_stats);
-		let doubles = BedwarsGameData::of("eight_two_", &bedwars_stats);
-		let trios = BedwarsGameData::of("four_three_", &bedwars_stats);
-		let fours = BedwarsGameData::of("four_four_", &bedwars_stats);
-		let four_v_four = BedwarsGameData::of("two_four_", &bedwars_stats);
-		let castle = BedwarsGameData::of("castle_", &bedwars_stats);
-		let doubles_lucky = BedwarsGameData::of("eight_two_lucky_", &bedwars_stats);
-		let fours_lucky = BedwarsGameData::of("four_four_lucky_", &bedwars_stats);
-		let doubles_ultimate = BedwarsGameData::of("eight_two_ultimate_", &bedwars_stats);
-		let fours_ultimate = BedwarsGameData::of("four_four_ultimate_", &bedwars_stats);
-		let doubles_armed = BedwarsGameData::of("eight_two_armed_", &bedwars_stats);
-		let fours_armed = BedwarsGameData::of("four_four_armed_", &bedwars_stats);
-		let doubles_rush = BedwarsGameData::of("eight_two_rush_", &bedwars_stats);
-		let fours_rush = BedwarsGameData::of("four_four_rush_", &bedwars_stats);
-		let doubles_swap = BedwarsGameData::of("eight_two_swap_", &bedwars_stats);
-		let fours_swap = BedwarsGameData::of("four_four_swap_", &bedwars_stats);
+		let solo = BedwarsGameData::of("eight_one_", bedwars_stats);
+		let doubles = BedwarsGameData::of("eight_two_", bedwars_stats);
+		let trios = BedwarsGameData::of("four_three_", bedwars_stats);
+		let fours = BedwarsGameData::of("four_four_", bedwars_stats);
+		let four_v_four = BedwarsGameData::of("two_four_", bedwars_stats);
+		let castle = BedwarsGameData::of("castle_", bedwars_stats);
+		let doubles_lucky = BedwarsGameData::of("eight_two_lucky_", bedwars_stats);
+		let fours_lucky = BedwarsGameData::of("four_four_lucky_", bedwars_stats);
+		let doubles_ultimate = BedwarsGameData::of("eight_two_ultimate_", bedwars_stats);
+		let fours_ultimate = BedwarsGameData::of("four_four_ultimate_", bedwars_stats);
+		let doubles_armed = BedwarsGameData::of("eight_two_armed_", bedwars_stats);
+		let fours_armed = BedwarsGameData::of("four_four_armed_", bedwars_stats);
+		let doubles_rush = BedwarsGameData::of("eight_two_rush_", bedwars_stats);
+		let fours_rush = BedwarsGameData::of("four_four_rush_", bedwars_stats);
+		let doubles_swap = BedwarsGameData::of("eight_two_swap_", bedwars_stats);
+		let fours_swap = BedwarsGameData::of("four_four_swap_", bedwars_stats);
 		BedwarsData {
 			level: val["achievements"]["bedwars_level"].as_i64().unwrap_or(-1),
-			all: BedwarsGameData::of("", &bedwars_stats),
+			all: BedwarsGameData::of("", bedwars_stats),
 			core: CombinedBedwarsGameData {
 				kills: solo.kills + doubles.kills + trios.kills + fours.kills,
 				deaths: solo.deaths + doubles.deaths + trios.deaths + fours.deaths,
@@ -682,9 +676,24 @@ mod colors {
 	}
 }
 
+/// Query cached values from the public Hypixel API
+#[utoipa::path(
+	get,
+	path = "/hypixel",
+	operation_id = "get_hypixel",
+	tag = "hypixel",
+	security(("access_token" = [])),
+	request_body = RequestDataType,
+	responses(
+		(status = OK, body = Object, description = "Varies for each `request_type`"),
+		(status = NOT_FOUND, description = "The player has no data for the requested type"),
+		(status = TOO_MANY_REQUESTS, description = "The Hypixel API ratelimit has been reached"),
+		(status = SERVICE_UNAVAILABLE, description = "No Hypixel API key is configured"),
+	)
+)]
 pub async fn get(
 	State(ApiState {
-		cl_args,
+		config,
 		hypixel_api_state,
 		client,
 		..
@@ -696,7 +705,7 @@ pub async fn get(
 	let player_data = match hypixel_api_cache.get(&request_data_type.target_player) {
 		Some(value) => value,
 		None => {
-			let value = fetch_data(cl_args.clone(), &hypixel_api_state.clone(), &client, &request_data_type).await?;
+			let value = fetch_data(config.clone(), &hypixel_api_state.clone(), &client, &request_data_type).await?;
 			hypixel_api_cache.insert(request_data_type.target_player, value.clone());
 			value
 		}
@@ -747,7 +756,7 @@ pub async fn get(
 }
 
 async fn fetch_data(
-	cl_args: Arc<ClArgs>,
+	config: Arc<Config>,
 	hypixel_api_state: &Arc<HypixelApiProxyState>,
 	client: &Client,
 	request_data_type: &RequestDataType,
@@ -773,18 +782,21 @@ async fn fetch_data(
 		return Err(response);
 	}
 
-	let api_key = match &cl_args.hypixel.hypixel_api_key {
+	let api_key = match &config.hypixel_api_key {
 		Some(api_key) => api_key,
-		None => match &cl_args.hypixel.hypixel_api_key_file {
+		None => match &config.hypixel_api_key_file {
 			Some(file) => &read_to_string(file)
 				.map(|s| s.trim().to_string())
 				.map_err(|e| ApiError::from(e).into_response())?,
-			None => unreachable!("clap should ensure that a url or url file is provided"),
+			None => {
+				warn!("Received a Hypixel request, but no API key is configured!");
+				return Err(StatusCode::SERVICE_UNAVAILABLE.into_response());
+			}
 		},
 	};
 
 	let response = client
-		.get(HYPIXEL_API_URL.to_string() + "/player")
+		.get(config.upstream.hypixel_api.to_string() + "/player")
 		.query(&[("uuid", request_data_type.target_player)])
 		.header("api-key", api_key)
 		.send()
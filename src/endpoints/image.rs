@@ -10,6 +10,7 @@ use chrono::{DateTime, Utc};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use sqlx::query;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
 use crate::{
@@ -19,14 +20,24 @@ use crate::{
 	id::Id,
 };
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct SharedImage {
 	uploader: Uuid,
 	filename: String,
+	/// The file content, encoded with standard base64
 	file: String,
 	shared_at: DateTime<Utc>,
 }
 
+/// Fetch a shared image with metadata
+#[utoipa::path(
+	get,
+	path = "/image/{id}",
+	operation_id = "get_image",
+	tag = "image",
+	params(("id" = u64, Path)),
+	responses((status = OK, body = SharedImage), (status = NOT_FOUND, description = "The image does not exist or has expired"))
+)]
 pub async fn get(
 	State(ApiState { database, .. }): State<ApiState>,
 	Path(id): Path<Id>,
@@ -45,6 +56,13 @@ pub async fn get(
 	}))
 }
 
+#[utoipa::path(
+	get,
+	path = "/image/{id}/raw",
+	tag = "image",
+	params(("id" = u64, Path)),
+	responses((status = OK, body = Vec<u8>, content_type = "image/png"), (status = NOT_FOUND, description = "The image does not exist or has expired"))
+)]
 pub async fn get_raw(
 	State(ApiState { database, .. }): State<ApiState>,
 	Path(id): Path<Id>,
@@ -56,6 +74,21 @@ pub async fn get_raw(
 	Ok(image.file)
 }
 
+/// Share an image in PNG format
+#[utoipa::path(
+	post,
+	path = "/image/{id}",
+	operation_id = "post_image",
+	tag = "image",
+	params(("id" = String, Path, description = "The name of the file to share")),
+	security(("access_token" = [])),
+	request_body(content = Vec<u8>, content_type = "image/png"),
+	responses(
+		(status = OK, body = String, content_type = "text/plain", description = "The id of the new image"),
+		(status = BAD_REQUEST, description = "The png file is malformed"),
+		(status = PAYLOAD_TOO_LARGE, description = "The image is over 8MiB in size"),
+	)
+)]
 pub async fn post(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -80,17 +113,31 @@ pub async fn post(
 	Ok(id.to_string())
 }
 
-pub async fn evict_expired(ApiState { database, .. }: &ApiState) -> Result<(), TaskError> {
-	query!("DELETE FROM images WHERE (LOCALTIMESTAMP - timestamp) > '1 week'")
-		.execute(database)
-		.await?;
+pub async fn evict_expired(ApiState { database, config, .. }: &ApiState) -> Result<(), TaskError> {
+	let retention = config.retention.images_seconds as f64;
+	// Images attached to messages are kept as long as the message exists
+	query!(
+		r#"DELETE FROM images WHERE (LOCALTIMESTAMP - timestamp) > make_interval(secs => $1)
+			AND NOT EXISTS (SELECT 1 FROM message_attachments WHERE message_attachments.image = images.id)"#,
+		retention
+	)
+	.execute(database)
+	.await?;
 	Ok(())
 }
 
 const PAGE_TEMPLATE: &str = include_str!("image_view.html");
 
+/// View an image in a browser, with embedding support
+#[utoipa::path(
+	get,
+	path = "/image/{id}/view",
+	tag = "image",
+	params(("id" = u64, Path)),
+	responses((status = OK, body = String, content_type = "text/html"), (status = NOT_FOUND, description = "The image does not exist or has expired"))
+)]
 pub async fn get_view(
-	State(ApiState { database, cl_args, .. }): State<ApiState>,
+	State(ApiState { database, config, .. }): State<ApiState>,
 	Path(id): Path<Id>,
 ) -> Result<Html<String>, ApiError> {
 	let image = query!("SELECT filename, player, timestamp, file FROM images WHERE id = $1", id as _)
@@ -99,7 +146,7 @@ pub async fn get_view(
 		.ok_or(StatusCode::NOT_FOUND)?;
 
 	let filename = String::from_utf8(image.filename).unwrap();
-	let base_url = match &cl_args.domain_name {
+	let base_url = match &config.domain_name {
 		Some(name) => {
 			let n = name.to_owned();
 			if !n.ends_with("/") { n + "/" } else { n }
@@ -134,10 +181,10 @@ pub async fn get_view(
 	))
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct OEmbed {
 	version: &'static str,
-	#[serde(rename(serialize = "type"))]
+	#[serde(rename = "type")]
 	_type: &'static str,
 	title: String,
 	url: String,
@@ -162,13 +209,27 @@ impl OEmbed {
 	}
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct OEmbedQuery {
+	/// The oEmbed format, only `json` is supported currently
 	format: String,
 }
 
+/// Get oEmbed information for an image, see <https://oembed.com>
+#[utoipa::path(
+	get,
+	path = "/image/{id}/oembed",
+	tag = "image",
+	params(("id" = u64, Path), OEmbedQuery),
+	responses(
+		(status = OK, body = OEmbed),
+		(status = NOT_FOUND, description = "The image does not exist or has expired"),
+		(status = NOT_IMPLEMENTED, description = "The requested format is not supported"),
+	)
+)]
 pub async fn get_oembed(
-	State(ApiState { database, cl_args, .. }): State<ApiState>,
+	State(ApiState { database, config, .. }): State<ApiState>,
 	Path(id): Path<Id>,
 	Query(OEmbedQuery { format }): Query<OEmbedQuery>,
 ) -> Result<Json<OEmbed>, ApiError> {
@@ -184,7 +245,7 @@ pub async fn get_oembed(
 
 	let filename = String::from_utf8(image.filename).unwrap();
 
-	let base_url = match &cl_args.domain_name {
+	let base_url = match &config.domain_name {
 		Some(name) => {
 			let n = name.to_owned();
 			if !n.ends_with("/") { n + "/" } else { n }
@@ -1,11 +1,17 @@
-use crate::{ApiState, errors::ApiError};
-use axum::{Json, extract::Query, extract::State, http::StatusCode, response::IntoResponse};
+use crate::{ApiState, config::Config, errors::ApiError, errors::TaskError, gateway};
+use axum::{
+	Json, extract::DefaultBodyLimit, extract::Query, extract::State, http::StatusCode, response::IntoResponse,
+	routing::any, routing::get,
+};
 use base64::{Engine, engine::general_purpose::STANDARD_NO_PAD};
 use blake2::{Blake2b512, Digest};
 use garde::Validate;
 use serde::{Deserialize, Serialize};
 use sqlx::{Type, error::ErrorKind::UniqueViolation, query, query_as, query_scalar};
 use std::ops::Deref;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, router::UtoipaMethodRouterExt, routes};
 use uuid::Uuid;
 
 pub mod account;
@@ -15,7 +21,82 @@ pub mod hypixel;
 pub mod image;
 pub mod user;
 
-#[derive(Clone, Deserialize, Serialize, Validate, Type)]
+#[derive(OpenApi)]
+#[openapi(
+	info(title = "AxolotlClient API", description = "API backend for AxolotlClient"),
+	modifiers(&AccessToken),
+	tags(
+		(name = "global", description = "Data about the backend and mod"),
+		(name = "authentication", description = "Authentication and the gateway"),
+		(name = "user", description = "Other users and relations to them"),
+		(name = "account", description = "The authenticated user's account"),
+		(name = "channel", description = "Chat channels and their messages"),
+		(name = "image", description = "Shared images"),
+		(name = "hypixel", description = "Cached Hypixel API data"),
+	)
+)]
+pub struct ApiDoc;
+
+/// Registers the `access_token` security scheme referenced by authenticated endpoints, the token returned from
+/// `/authenticate` is sent as-is in the `Authorization` header.
+struct AccessToken;
+
+impl Modify for AccessToken {
+	fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+		openapi.components.get_or_insert_default().add_security_scheme(
+			"access_token",
+			SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("Authorization"))),
+		);
+	}
+}
+
+/// Routes of the `v1` API, breaking changes to these must instead be made in a new version, see [`crate::version`].
+pub fn v1(config: &Config) -> OpenApiRouter<ApiState> {
+	OpenApiRouter::new()
+		.routes(routes!(global_data::get))
+		.routes(routes!(global_data::metrics))
+		.routes(routes!(get_authenticate))
+		.route("/gateway", any(gateway::gateway))
+		.merge(OpenApiRouter::with_openapi(gateway::GatewayDoc::openapi()))
+		.routes(routes!(user::get, user::post))
+		.routes(routes!(user::get_images))
+		.routes(routes!(user::post_dm))
+		.routes(routes!(account::get_channels))
+		.routes(routes!(channel::search))
+		.routes(routes!(account::get_dms))
+		.routes(routes!(account::get_channel_invites, account::post_channel_invite))
+		.routes(routes!(channel::post))
+		.routes(routes!(channel::get, channel::post_channel, channel::patch, channel::delete))
+		.routes(routes!(channel::get_messages))
+		.routes(routes!(channel::patch_message, channel::delete_message))
+		.routes(routes!(channel::put_reaction, channel::delete_reaction))
+		.routes(routes!(channel::get_pins))
+		.routes(routes!(channel::put_pin, channel::delete_pin))
+		.routes(routes!(channel::post_ack))
+		.routes(routes!(channel::remove_user))
+		.routes(routes!(channel::put_role))
+		.routes(routes!(channel::put_owner))
+		.routes(routes!(channel::post_invite_code, channel::get_invite_codes))
+		.routes(routes!(channel::delete_invite_code))
+		.routes(routes!(channel::get_invite, channel::post_invite))
+		.routes(routes!(account::get, account::delete))
+		.routes(routes!(account::post_activity))
+		.routes(routes!(account::get_data))
+		.routes(routes!(account::get_settings, account::patch_settings))
+		.routes(routes!(account::post_username, account::delete_username))
+		.routes(routes!(account::get_friends))
+		.routes(routes!(account::get_blocked))
+		.routes(routes!(account::get_requests))
+		.routes(routes!(image::get, image::post).layer(DefaultBodyLimit::max(config.limits.image_size_bytes)))
+		.routes(routes!(image::get_raw))
+		.routes(routes!(image::get_view))
+		.routes(routes!(image::get_oembed))
+		.routes(routes!(hypixel::get))
+		//.route("/report/:message", post(channel::report_message))
+		.route("/brew_coffee", get(brew_coffee).post(brew_coffee))
+}
+
+#[derive(Clone, Deserialize, Serialize, Validate, Type, ToSchema)]
 #[repr(transparent)]
 #[serde(transparent)]
 #[sqlx(transparent)]
@@ -41,23 +122,42 @@ impl Deref for Username {
 	}
 }
 
-#[derive(Deserialize, Validate)]
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Authenticate {
 	#[garde(dive)]
+	#[param(value_type = String)]
 	username: Username,
+	/// Server Id used to validate authentication with Mojang, this should be a secret random string
 	#[garde(skip)] // No documented limits ¯\_(ツ)_/¯
 	server_id: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct AuthenticateResponse {
 	uuid: Uuid,
 	username: Username,
 	access_token: String,
 }
 
+#[utoipa::path(
+	get,
+	path = "/authenticate",
+	tag = "authentication",
+	params(Authenticate),
+	responses(
+		(status = OK, body = AuthenticateResponse),
+		(status = UNAUTHORIZED, description = "Mojang did not confirm the session"),
+		(status = FORBIDDEN, description = "The user is banned"),
+	)
+)]
 pub async fn get_authenticate(
-	State(ApiState { database, client, .. }): State<ApiState>,
+	State(ApiState {
+		database,
+		client,
+		config,
+		..
+	}): State<ApiState>,
 	Query(authenticate): Query<Authenticate>,
 ) -> Result<Json<AuthenticateResponse>, ApiError> {
 	authenticate.validate()?;
@@ -72,7 +172,7 @@ pub async fn get_authenticate(
 	}
 
 	let response = client
-		.get("https://sessionserver.mojang.com/session/minecraft/hasJoined")
+		.get(format!("{}/hasJoined", config.upstream.mojang_session_server))
 		.query(&[("username", &*username), ("serverId", &server_id)])
 		.send()
 		.await?;
@@ -88,16 +188,16 @@ pub async fn get_authenticate(
 	let banned = query_scalar!("SELECT banned FROM players WHERE uuid = $1", &user.uuid)
 		.fetch_optional(&mut *transaction)
 		.await?;
-	if let Some(val) = banned {
-		if val {
-			// User is banned, revoke all tokens
-			query!("UPDATE tokens SET revoked = true WHERE player = $1", user.uuid)
-				.execute(&mut *transaction)
-				.await?;
+	if let Some(val) = banned
+		&& val
+	{
+		// User is banned, revoke all tokens
+		query!("UPDATE tokens SET revoked = true WHERE player = $1", user.uuid)
+			.execute(&mut *transaction)
+			.await?;
 
-			transaction.commit().await?;
-			return Err(StatusCode::FORBIDDEN)?;
-		}
+		transaction.commit().await?;
+		return Err(StatusCode::FORBIDDEN)?;
 	}
 
 	let mut usernames_to_update = vec![user.clone()];
@@ -116,8 +216,8 @@ pub async fn get_authenticate(
 			Some(existing_player_with_name) => {
 				let existing_player_with_name = client
 					.get(format!(
-						"https://sessionserver.mojang.com/session/minecraft/profile/{}",
-						existing_player_with_name.uuid
+						"{}/profile/{}",
+						config.upstream.mojang_session_server, existing_player_with_name.uuid
 					))
 					.send()
 					.await?
@@ -135,16 +235,16 @@ pub async fn get_authenticate(
 				.fetch_optional(&mut *transaction)
 				.await?;
 
-				if let Some(previous_username) = previous_username {
-					if previous_username != String::from(player_to_update.username.clone()) {
-						query!(
-							"INSERT INTO previous_usernames(player, username) VALUES ($1, $2)",
-							player_to_update.uuid,
-							previous_username
-						)
-						.execute(&mut *transaction)
-						.await?;
-					}
+				if let Some(previous_username) = previous_username
+					&& previous_username != String::from(player_to_update.username.clone())
+				{
+					query!(
+						"INSERT INTO previous_usernames(player, username) VALUES ($1, $2)",
+						player_to_update.uuid,
+						previous_username
+					)
+					.execute(&mut *transaction)
+					.await?;
 				}
 
 				query!(
@@ -204,6 +304,15 @@ pub async fn get_authenticate(
 	}))
 }
 
+/// Deletes revoked tokens and tokens older than a day. The generated `expired` column only changes when a token is
+/// used, so tokens that were never used again are caught by their age.
+pub async fn evict_expired_tokens(ApiState { database, .. }: &ApiState) -> Result<(), TaskError> {
+	query!("DELETE FROM tokens WHERE revoked OR LOCALTIMESTAMP - created > '1 day'")
+		.execute(database)
+		.await?;
+	Ok(())
+}
+
 pub async fn brew_coffee() -> impl IntoResponse {
 	(StatusCode::IM_A_TEAPOT, "I'm a Teapot")
 }
@@ -1,3 +1,4 @@
+use super::channel;
 use crate::{ApiState, errors::ApiError, extractors::Authentication};
 use axum::{
 	Json,
@@ -8,10 +9,11 @@ use log::warn;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use sqlx::{Type, query, query_scalar};
+use sqlx::{PgExecutor, Type, query, query_scalar};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct User {
 	uuid: Uuid,
 
@@ -29,7 +31,7 @@ pub struct User {
 	previous_usernames: Vec<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum Status {
 	Offline {
@@ -42,16 +44,17 @@ pub enum Status {
 	},
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 pub struct Activity {
 	title: String,
 	description: String,
 	started: DateTime<Utc>,
 	#[serde(skip_serializing_if = "Value::is_null", default)]
+	#[schema(value_type = Option<Object>)]
 	metadata: Value,
 }
 
-#[derive(Deserialize, Serialize, Type)]
+#[derive(Deserialize, Serialize, Type, ToSchema)]
 #[serde(rename_all = "lowercase")]
 #[sqlx(type_name = "relation", rename_all = "lowercase")]
 pub enum Relation {
@@ -61,6 +64,15 @@ pub enum Relation {
 	Friend,
 }
 
+#[utoipa::path(
+	get,
+	path = "/user/{uuid}",
+	operation_id = "get_user",
+	tag = "user",
+	params(("uuid" = Uuid, Path)),
+	security((), ("access_token" = [])),
+	responses((status = OK, body = User), (status = NOT_FOUND))
+)]
 pub async fn get(
 	State(ApiState {
 		database, online_users, ..
@@ -86,6 +98,12 @@ pub async fn get(
 	.await?
 	.ok_or(StatusCode::NOT_FOUND)?;
 
+	// Users who blocked the authenticated user always appear offline to them
+	let blocked_by = match &authentication {
+		Some(Authentication(uuid)) => blocked_by(&mut *transaction, uuid).await?.contains(&other_uuid),
+		None => false,
+	};
+
 	let relation = if let Some(Authentication(uuid)) = authentication {
 		Some(
 			query_scalar!(
@@ -102,6 +120,7 @@ pub async fn get(
 	};
 
 	let status = match online_users.get(&other_uuid) {
+		_ if blocked_by => Status::Offline { last_online: None },
 		None => {
 			let last_online = match user.show_last_online {
 				true => user.last_online.map(|dt| dt.and_utc()),
@@ -141,7 +160,8 @@ pub async fn get(
 	}))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct PostRelation {
 	relation: Relation,
 }
@@ -152,6 +172,20 @@ pub struct FriendRequestNotification {
 	from: Uuid,
 }
 
+#[utoipa::path(
+	post,
+	path = "/user/{uuid}",
+	operation_id = "post_user",
+	tag = "user",
+	params(("uuid" = Uuid, Path), PostRelation),
+	security(("access_token" = [])),
+	responses(
+		(status = NO_CONTENT),
+		(status = BAD_REQUEST, description = "The authenticated user and the queried user are the same"),
+		(status = FORBIDDEN, description = "The other user has not sent a friend request, or has blocked the authenticated user"),
+		(status = NOT_FOUND, description = "The queried user isn't known to the database"),
+	)
+)]
 pub async fn post(
 	State(ApiState {
 		database,
@@ -186,6 +220,14 @@ pub async fn post(
 			)
 			.execute(&mut *transaction)
 			.await?;
+
+			query!(
+				"DELETE FROM channel_invites WHERE (player = $1 AND sender = $2) OR (player = $2 AND sender = $1)",
+				uuid,
+				other_uuid
+			)
+			.execute(&mut *transaction)
+			.await?;
 		}
 		Relation::None => {
 			let other_relation = query_scalar!(
@@ -196,20 +238,19 @@ pub async fn post(
 			.fetch_optional(&mut *transaction)
 			.await?
 			.unwrap_or(Relation::None);
-			if let Relation::Request = other_relation {
-				if online_users.contains_key(&other_uuid) {
-					if let Some(sender) = socket_sender.get(&other_uuid) {
-						sender
-							.send(
-								serde_json::to_string(&FriendRequestNotification {
-									target: "friend_request_deny".to_string(),
-									from: uuid,
-								})
-								.unwrap(),
-							)
-							.unwrap();
-					}
-				}
+			if let Relation::Request = other_relation
+				&& online_users.contains_key(&other_uuid)
+				&& let Some(sender) = socket_sender.get(&other_uuid)
+			{
+				sender
+					.send(
+						serde_json::to_string(&FriendRequestNotification {
+							target: "friend_request_deny".to_string(),
+							from: uuid,
+						})
+						.unwrap(),
+					)
+					.unwrap();
 			}
 			query!("DELETE FROM relations WHERE player_a = $1 AND player_b = $2", uuid, other_uuid)
 				.execute(&mut *transaction)
@@ -249,18 +290,18 @@ pub async fn post(
 						.await?;
 
 					// Notify $other_uuid that they have a new friend request (as there hasn't yet been a relation between the two)
-					if online_users.contains_key(&other_uuid) {
-						if let Some(sender) = socket_sender.get(&other_uuid) {
-							sender
-								.send(
-									serde_json::to_string(&FriendRequestNotification {
-										target: "friend_request".to_string(),
-										from: uuid,
-									})
-									.unwrap(),
-								)
-								.unwrap();
-						}
+					if online_users.contains_key(&other_uuid)
+						&& let Some(sender) = socket_sender.get(&other_uuid)
+					{
+						sender
+							.send(
+								serde_json::to_string(&FriendRequestNotification {
+									target: "friend_request".to_string(),
+									from: uuid,
+								})
+								.unwrap(),
+							)
+							.unwrap();
 					}
 				}
 
@@ -318,18 +359,18 @@ pub async fn post(
 					.await?;
 
 					// Notify $other_uuid that the request has been accepted
-					if online_users.contains_key(&other_uuid) {
-						if let Some(sender) = socket_sender.get(&other_uuid) {
-							sender
-								.send(
-									serde_json::to_string(&FriendRequestNotification {
-										target: "friend_request_accept".to_string(),
-										from: uuid,
-									})
-									.unwrap(),
-								)
-								.unwrap();
-						}
+					if online_users.contains_key(&other_uuid)
+						&& let Some(sender) = socket_sender.get(&other_uuid)
+					{
+						sender
+							.send(
+								serde_json::to_string(&FriendRequestNotification {
+									target: "friend_request_accept".to_string(),
+									from: uuid,
+								})
+								.unwrap(),
+							)
+							.unwrap();
 					}
 				}
 
@@ -344,6 +385,17 @@ pub async fn post(
 	Ok(StatusCode::NO_CONTENT)
 }
 
+#[utoipa::path(
+	get,
+	path = "/user/{uuid}/images",
+	tag = "user",
+	params(("uuid" = Uuid, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = OK, body = Vec<u64>, description = "Ids of the images the user has uploaded"),
+		(status = FORBIDDEN, description = "The authenticated user isn't friends with the queried user"),
+	)
+)]
 pub async fn get_images(
 	State(ApiState { database, .. }): State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -379,5 +431,76 @@ pub async fn get_images(
 
 		return Ok(Json(images));
 	}
-	return Err(StatusCode::FORBIDDEN)?;
+	Err(StatusCode::FORBIDDEN)?
+}
+
+/// Get the direct message channel with a friend, creating it if it does not exist yet. Direct message channels are
+/// listed by `/account/dms` instead of `/channels`.
+#[utoipa::path(
+	post,
+	path = "/user/{uuid}/dm",
+	tag = "user",
+	params(("uuid" = Uuid, Path)),
+	security(("access_token" = [])),
+	responses(
+		(status = OK, body = String, content_type = "text/plain", description = "The id of the direct message channel"),
+		(status = BAD_REQUEST, description = "The authenticated user and the other user are the same"),
+		(status = FORBIDDEN, description = "The users are not friends"),
+	)
+)]
+pub async fn post_dm(
+	State(ApiState { database, .. }): State<ApiState>,
+	Authentication(uuid): Authentication,
+	Path(other_uuid): Path<Uuid>,
+) -> Result<String, ApiError> {
+	if uuid == other_uuid {
+		Err(StatusCode::BAD_REQUEST)?
+	}
+
+	if !are_friends(&database, &uuid, &other_uuid).await? {
+		Err(StatusCode::FORBIDDEN)?
+	}
+
+	Ok(channel::get_or_create_dm(&database, &uuid, &other_uuid)
+		.await?
+		.to_string())
+}
+
+/// Whether two users are friends, neither of them can have blocked the other as blocking either way removes friendships
+pub async fn are_friends<'e>(executor: impl PgExecutor<'e>, uuid: &Uuid, other: &Uuid) -> Result<bool, sqlx::Error> {
+	// Friendships are stored in both directions
+	let friends = query_scalar!(
+		r#"SELECT COUNT(*) AS "count!" FROM relations WHERE relation = 'friend'
+			AND ((player_a = $1 AND player_b = $2) OR (player_a = $2 AND player_b = $1))"#,
+		uuid,
+		other
+	)
+	.fetch_one(executor)
+	.await?;
+	Ok(friends == 2)
+}
+
+/// Users who blocked the given user
+pub async fn blocked_by<'e>(executor: impl PgExecutor<'e>, uuid: &Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+	query_scalar!("SELECT player_a FROM relations WHERE player_b = $1 AND relation = 'blocked'", uuid)
+		.fetch_all(executor)
+		.await
+}
+
+/// Users who were blocked by the given user
+pub async fn blocked<'e>(executor: impl PgExecutor<'e>, uuid: &Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+	query_scalar!("SELECT player_b FROM relations WHERE player_a = $1 AND relation = 'blocked'", uuid)
+		.fetch_all(executor)
+		.await
+}
+
+/// Users who blocked, or were blocked by, the given user
+pub async fn blocks<'e>(executor: impl PgExecutor<'e>, uuid: &Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+	query_scalar!(
+		r#"SELECT CASE WHEN player_a = $1 THEN player_b ELSE player_a END AS "player!" FROM relations
+			WHERE relation = 'blocked' AND (player_a = $1 OR player_b = $1)"#,
+		uuid
+	)
+	.fetch_all(executor)
+	.await
 }
@@ -4,7 +4,7 @@ use axum::{
 };
 use garde::Report;
 use log::error;
-use std::error::Error;
+use std::{error::Error, fmt::Display, fmt::Formatter};
 
 pub struct ApiError(Response);
 
@@ -63,17 +63,24 @@ impl ApiError {
 	}
 }
 
-pub struct TaskError;
+/// An error while running a background task, logged and recorded in `task_runs` by the scheduler
+pub struct TaskError(String);
 
-impl TaskError {
-	fn handle<E: Error>(error: E) -> TaskError {
-		error!("Error while running taks: {error}");
-		TaskError
+impl Display for TaskError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.0)
 	}
 }
 
 impl From<sqlx::Error> for TaskError {
 	fn from(value: sqlx::Error) -> Self {
-		Self::handle(value)
+		Self(value.to_string())
+	}
+}
+
+impl From<ApiError> for TaskError {
+	fn from(value: ApiError) -> Self {
+		// Internal errors are already logged when the ApiError is created
+		Self(format!("request failed with status {}", value.0.status()))
 	}
 }
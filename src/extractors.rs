@@ -28,8 +28,9 @@ impl FromRequestParts<ApiState> for Authentication {
 		let authorization_ref = &*authorization;
 		let uuid = {
 			let record = query!("SELECT valid, player FROM tokens WHERE token = $1", authorization_ref)
-				.fetch_one(&mut *transaction)
-				.await?;
+				.fetch_optional(&mut *transaction)
+				.await?
+				.ok_or(StatusCode::UNAUTHORIZED)?;
 
 			match record.valid {
 				true => match record.player {
@@ -8,8 +8,23 @@ use sqlx::query;
 use std::{convert::Infallible, fmt::Display, fmt::Formatter, time::Duration};
 use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
 use tokio::{pin, select, time::Instant, time::sleep};
+use tokio_util::sync::CancellationToken;
+use utoipa::OpenApi;
 use uuid::Uuid;
 
+/// Documents [`gateway`] on its own, the route itself accepts any method so that HTTP/2 extended CONNECT upgrades
+/// keep working, which a `routes!` registration would restrict to `GET`.
+#[derive(OpenApi)]
+#[openapi(paths(gateway))]
+pub struct GatewayDoc;
+
+#[utoipa::path(
+	get,
+	path = "/gateway",
+	tag = "authentication",
+	security(("access_token" = [])),
+	responses((status = SWITCHING_PROTOCOLS, description = "Switch to WebSocket"))
+)]
 pub async fn gateway(
 	state: State<ApiState>,
 	Authentication(uuid): Authentication,
@@ -22,7 +37,8 @@ pub async fn gateway(
 		drop(socket);
 	}
 
-	Ok(socket.on_upgrade(move |socket| gateway_accept_handler(state, uuid, socket, agent)))
+	let sessions = state.gateway_sessions.clone();
+	Ok(socket.on_upgrade(move |socket| sessions.track_future(gateway_accept_handler(state, uuid, socket, agent))))
 }
 
 async fn gateway_accept_handler(
@@ -31,6 +47,8 @@ async fn gateway_accept_handler(
 		online_users,
 		socket_sender,
 		global_data,
+		config,
+		shutdown,
 		..
 	}): State<ApiState>,
 	uuid: Uuid,
@@ -53,7 +71,10 @@ async fn gateway_accept_handler(
 		drop(container);
 	}
 
-	let disconnect_reason = gateway_accept(&mut socket, &mut receiver).await.unwrap_err();
+	let keep_alive = config.timeouts.gateway_keep_alive();
+	let disconnect_reason = gateway_accept(&mut socket, &mut receiver, keep_alive, &shutdown)
+		.await
+		.unwrap_err();
 	let _ = socket
 		.send(Message::Close(Some(CloseFrame {
 			code: disconnect_reason as u16,
@@ -86,14 +107,17 @@ async fn gateway_accept_handler(
 async fn gateway_accept(
 	socket: &mut WebSocket,
 	receiver: &mut UnboundedReceiver<String>,
+	keep_alive_duration: Duration,
+	shutdown: &CancellationToken,
 ) -> Result<Infallible, DisconnectReason> {
 	let mut pending_pong: Option<[u8; 32]> = None;
-	let keep_alive = sleep(Duration::from_secs(10));
+	let keep_alive = sleep(keep_alive_duration);
 	pin!(keep_alive);
 
 	loop {
 		select! {
 			biased;
+			_ = shutdown.cancelled() => return Err(Restarting),
 			message = socket.recv() => {
 				match message.ok_or(Closed)?? {
 					Message::Text(data) => {
@@ -120,7 +144,7 @@ async fn gateway_accept(
 					Message::Close(_) => return Err(Closed),
 				}
 
-				keep_alive.as_mut().reset(Instant::now() + Duration::from_secs(10));
+				keep_alive.as_mut().reset(Instant::now() + keep_alive_duration);
 				pending_pong = None;
 			}
 			socket_message = receiver.recv() => {
@@ -135,7 +159,7 @@ async fn gateway_accept(
 						let ping = rand::random();
 						socket.send(Message::Ping(Vec::from(&ping).into())).await?;
 						pending_pong = Some(ping);
-						keep_alive.as_mut().reset(Instant::now() + Duration::from_secs(10));
+						keep_alive.as_mut().reset(Instant::now() + keep_alive_duration);
 					}
 					Some(_) => return Err(TimedOut),
 				}
@@ -150,6 +174,7 @@ enum DisconnectReason {
 	Closed = close_code::NORMAL,
 	Error = close_code::ERROR,
 	InvalidData = close_code::INVALID,
+	Restarting = close_code::RESTART,
 	TimedOut = 1014, // There is no pre-defined code for timeouts
 }
 
@@ -159,6 +184,7 @@ impl Display for DisconnectReason {
 			Closed => write!(f, "Closed"),
 			Error => write!(f, "Error"),
 			InvalidData => write!(f, "Invalid Data"),
+			Restarting => write!(f, "Server Restarting"),
 			TimedOut => write!(f, "Timed Out"),
 		}
 	}
@@ -169,3 +195,21 @@ impl From<axum::Error> for DisconnectReason {
 		Error
 	}
 }
+
+/// Writes `last_online` for sessions which did not close before the shutdown deadline, sessions that did have already
+/// written it themselves.
+pub async fn flush_last_online(
+	ApiState {
+		database, online_users, ..
+	}: &ApiState,
+) -> Result<(), sqlx::Error> {
+	let uuids: Vec<Uuid> = online_users.iter().map(|entry| *entry.key()).collect();
+	if uuids.is_empty() {
+		return Ok(());
+	}
+
+	query!("UPDATE players SET last_online = 'now' WHERE uuid = ANY($1) AND show_last_online = true", &uuids)
+		.execute(database)
+		.await?;
+	Ok(())
+}
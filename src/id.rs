@@ -1,9 +1,10 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{Database, Decode, Encode, Type, encode::IsNull, error::BoxDynError};
 use std::{cell::Cell, cell::RefCell, ops::Deref, sync::atomic::AtomicU8, sync::atomic::Ordering::Relaxed};
+use utoipa::ToSchema;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 #[repr(transparent)]
 pub struct Id(u64);
 
@@ -33,6 +34,18 @@ impl Id {
 
 		Id(timestamp | thread_id | counter)
 	}
+
+	/// The lowest id that could have been created at the given time, clamped to what fits in a `BIGINT`
+	pub fn from_timestamp(timestamp: DateTime<Utc>) -> Self {
+		let millis = timestamp.timestamp_millis().clamp(0, i64::MAX >> 22) as u64;
+		Id(millis << 22)
+	}
+}
+
+impl From<u64> for Id {
+	fn from(value: u64) -> Self {
+		Id(value)
+	}
 }
 
 impl<D: Database> Type<D> for Id
@@ -1,33 +1,42 @@
-use crate::endpoints::global_data::{self, GlobalDataContainer, RequestUserAgentCounter};
-use crate::endpoints::user::{self, Activity};
-use crate::endpoints::{account, brew_coffee, channel, get_authenticate, image, not_found};
-use crate::gateway::gateway;
-use axum::extract::DefaultBodyLimit;
-use axum::routing::any;
-use axum::{Router, routing::get, routing::post, serve};
+use crate::config::Config;
+use crate::endpoints::global_data::{GlobalDataContainer, RequestUserAgentCounter};
+use crate::endpoints::not_found;
+use crate::endpoints::user::Activity;
+use axum::{Json, routing::get, serve};
 use clap::{Args, Parser};
 use dashmap::DashMap;
-use endpoints::hypixel::{self, HypixelApiProxyState};
+use endpoints::hypixel::HypixelApiProxyState;
 use env_logger::Env;
-use log::info;
+use log::{info, warn};
 use reqwest::Client;
-use sqlx::{PgPool, migrate, postgres::PgConnectOptions};
-use std::time::{Duration, Instant};
-use std::{fs::read_to_string, path::PathBuf, str::FromStr, sync::Arc};
+use sqlx::{PgPool, migrate, postgres::PgPoolOptions};
+use std::future::IntoFuture;
+use std::time::Instant;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::RwLock;
 use tokio::sync::mpsc::UnboundedSender;
-use tokio::time::{MissedTickBehavior, interval};
+use tokio::time::sleep;
+use tokio::{select, signal::ctrl_c};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use uuid::Uuid;
 
+mod config;
 mod endpoints;
 mod errors;
 mod extractors;
 mod gateway;
 mod id;
+mod tasks;
+mod version;
 
 #[derive(Parser)]
 #[command(version)]
 pub struct ClArgs {
+	/// TOML configuration file, options given on the command line take precedence over it
+	#[arg(long)]
+	pub config: Option<PathBuf>,
+
 	#[group(flatten)]
 	pub postgres: PostgreSQL,
 
@@ -40,16 +49,19 @@ pub struct ClArgs {
 	#[arg(long)]
 	pub domain_name: Option<String>,
 
-	#[arg(long, default_value = "1073741824")]
-	pub cache_limit_bytes: u64,
+	#[arg(long)]
+	pub cache_limit_bytes: Option<u64>,
+
+	#[arg(long)]
+	pub bind_address: Option<SocketAddr>,
 }
 
 #[derive(Args)]
-#[group(required = true, multiple = false)]
+#[group(required = false, multiple = false)]
 pub struct PostgreSQL {
 	/// Postgres Connection Url, see: <https://docs.rs/sqlx/latest/sqlx/postgres/struct.PgConnectOptions.html>
 	#[arg(long)]
-	pub postgres_url: Option<PgConnectOptions>,
+	pub postgres_url: Option<String>,
 
 	/// File containing a Postgres Connection Url, see: <https://docs.rs/sqlx/latest/sqlx/postgres/struct.PgConnectOptions.html>
 	#[arg(long)]
@@ -71,110 +83,99 @@ pub struct Hypixel {
 #[derive(Clone)]
 pub struct ApiState {
 	pub database: PgPool,
-	pub cl_args: Arc<ClArgs>,
+	pub config: Arc<Config>,
 	pub client: Client,
 	pub online_users: Arc<DashMap<Uuid, Option<Activity>>>,
 	pub socket_sender: Arc<DashMap<Uuid, UnboundedSender<String>>>,
 	pub global_data: Arc<RwLock<GlobalDataContainer>>,
 	pub hypixel_api_state: Arc<HypixelApiProxyState>,
+	pub shutdown: CancellationToken,
+	pub gateway_sessions: TaskTracker,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
 	let start_time = Instant::now();
 
-	let cl_args = Arc::new(ClArgs::parse());
+	let config = Arc::new(Config::load(ClArgs::parse())?);
 
 	env_logger::init_from_env(Env::default().default_filter_or("info,tokio=trace,runtime=trace"));
 
 	info!("AxolotlClient-Api v{} ({})", env!("CARGO_PKG_VERSION"), env!("GIT_HASH"));
 
-	let database = {
-		let postgres_url = match &cl_args.postgres.postgres_url {
-			Some(postgres_url) => postgres_url.clone(),
-			None => match &cl_args.postgres.postgres_url_file {
-				Some(file) => PgConnectOptions::from_str(&read_to_string(file)?)?,
-				None => unreachable!("clap should ensure that a url or url file is provided"),
-			},
-		};
-
-		PgPool::connect_with(postgres_url.application_name("axolotl_client-api")).await?
-	};
+	let database = PgPoolOptions::new()
+		.acquire_timeout(config.timeouts.database_acquire())
+		.connect_with(
+			config
+				.postgres_connect_options()?
+				.application_name("axolotl_client-api"),
+		)
+		.await?;
 
 	migrate!().run(&database).await?;
 
 	let state = ApiState {
 		database,
-		hypixel_api_state: Arc::new(HypixelApiProxyState::new(cl_args.cache_limit_bytes)),
-		cl_args,
+		hypixel_api_state: Arc::new(HypixelApiProxyState::new(config.limits.hypixel_cache_bytes)),
 		client: Client::builder()
 			.user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+			.timeout(config.timeouts.upstream())
 			.build()?,
+		config: config.clone(),
 		online_users: Default::default(),
 		socket_sender: Default::default(),
 		global_data: Default::default(),
+		shutdown: CancellationToken::new(),
+		gateway_sessions: TaskTracker::new(),
 	};
 
-	let task_state = state.clone();
-	tokio::spawn(async move {
-		let mut interval = interval(Duration::from_secs(1 * 24 * 60 * 60));
-		interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
-		let tasks = [image::evict_expired];
-		loop {
-			let _ = interval.tick().await;
-			for task in tasks {
-				let _ = task(&task_state).await;
-			}
-		}
-	});
+	tasks::spawn(&state);
 
-	let router = Router::new()
-		.route("/global_data", get(global_data::get))
-		.route("/metrics", get(global_data::metrics))
-		.route("/authenticate", get(get_authenticate))
-		.route("/gateway", any(gateway))
-		.route("/user/{uuid}", get(user::get).post(user::post))
-		.route("/user/{uuid}/images", get(user::get_images))
-		.route("/channels", get(account::get_channels))
-		.route("/channels/invites", get(account::get_channel_invites).post(account::post_channel_invite))
-		.route("/channel", post(channel::post))
-		.route(
-			"/channel/{id}",
-			get(channel::get)
-				.post(channel::post_channel)
-				.patch(channel::patch)
-				.delete(channel::delete),
-		)
-		.route("/channel/{id}/messages", get(channel::get_messages))
-		.route("/channel/{id}/remove", post(channel::remove_user))
-		.route("/account", get(account::get).delete(account::delete))
-		.route("/account/activity", post(account::post_activity))
-		.route("/account/data", get(account::get_data))
-		.route("/account/settings", get(account::get_settings).patch(account::patch_settings))
-		.route("/account/username/{username}", post(account::post_username).delete(account::delete_username))
-		.route("/account/relations/friends", get(account::get_friends))
-		.route("/account/relations/blocked", get(account::get_blocked))
-		.route("/account/relations/requests", get(account::get_requests))
-		.route(
-			"/image/{id}",
-			get(image::get)
-				.post(image::post)
-				.layer(DefaultBodyLimit::max(1024 * 1024 * 8)),
-		)
-		.route("/image/{id}/raw", get(image::get_raw))
-		.route("/image/{id}/view", get(image::get_view))
-		.route("/image/{id}/oembed", get(image::get_oembed))
-		.route("/hypixel", get(hypixel::get))
-		//.route("/report/:message", post(channel::report_message))
-		.route("/brew_coffee", get(brew_coffee).post(brew_coffee))
+	let (router, openapi) = version::router(&config);
+
+	let router = router
+		.route("/openapi.json", get(move || async move { Json(openapi) }))
 		.layer(axum::middleware::from_extractor_with_state::<RequestUserAgentCounter, ApiState>(state.clone()))
 		.fallback(not_found)
-		.with_state(state);
+		.with_state(state.clone());
 
-	let listener = tokio::net::TcpListener::bind("[::]:8000").await?;
+	let listener = tokio::net::TcpListener::bind(config.server.bind_address).await?;
+
+	let shutdown = state.shutdown.clone();
+	let mut terminate = signal(SignalKind::terminate())?;
+	tokio::spawn(async move {
+		select! {
+			_ = ctrl_c() => {}
+			_ = terminate.recv() => {}
+		}
+		info!("Shutting down");
+		shutdown.cancel();
+	});
 
 	info!("Ready {:.0?}", Instant::now() - start_time);
 
-	serve(listener, router).await?;
-	Ok(())
+	// Gateway sessions are closed by the shutdown token as well, but are not tracked by `serve` once upgraded
+	let server = serve(listener, router).with_graceful_shutdown(state.shutdown.clone().cancelled_owned());
+	let drained = async {
+		server.into_future().await?;
+		state.gateway_sessions.close();
+		state.gateway_sessions.wait().await;
+		anyhow::Ok(())
+	};
+	let deadline = async {
+		state.shutdown.cancelled().await;
+		sleep(config.timeouts.shutdown()).await;
+	};
+	let result = select! {
+		result = drained => result,
+		_ = deadline => {
+			warn!("Shutdown deadline reached, dropping remaining connections");
+			Ok(())
+		}
+	};
+
+	// Flushed before the result is propagated, a failing `serve` is exactly when `last_online` would otherwise be lost
+	let flushed = gateway::flush_last_online(&state).await;
+	result?;
+	Ok(flushed?)
 }
@@ -0,0 +1,111 @@
+use crate::endpoints::{self, channel, global_data, image};
+use crate::{ApiState, errors::TaskError};
+use log::{debug, error};
+use sqlx::query;
+use std::{future::Future, pin::Pin, time::Duration};
+use tokio::{select, time::Instant, time::sleep};
+
+type TaskFuture<'a> = Pin<Box<dyn Future<Output = Result<(), TaskError>> + Send + 'a>>;
+
+pub struct Task {
+	/// Identifies the task in `task_runs` and on `/metrics`
+	pub name: &'static str,
+	/// Time between the end of one run and the start of the next, before jitter
+	pub interval: Duration,
+	pub run: for<'a> fn(&'a ApiState) -> TaskFuture<'a>,
+}
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+
+pub const TASKS: &[Task] = &[
+	Task {
+		name: "image_eviction",
+		interval: Duration::from_secs(HOUR),
+		run: |state| Box::pin(image::evict_expired(state)),
+	},
+	Task {
+		name: "message_persistence",
+		interval: Duration::from_secs(10 * MINUTE),
+		run: |state| Box::pin(channel::enforce_persistence(state)),
+	},
+	Task {
+		name: "token_cleanup",
+		interval: Duration::from_secs(24 * HOUR),
+		run: |state| Box::pin(endpoints::evict_expired_tokens(state)),
+	},
+	Task {
+		name: "invite_code_cleanup",
+		interval: Duration::from_secs(24 * HOUR),
+		run: |state| Box::pin(channel::evict_expired_invite_codes(state)),
+	},
+	Task {
+		name: "cache_warmup",
+		interval: Duration::from_secs(6 * HOUR),
+		run: |state| Box::pin(global_data::warm_cache(state)),
+	},
+];
+
+/// Runs every task once on startup and then repeatedly until shutdown. Up to a tenth of the interval is added at
+/// random to every wait, so that instances sharing a database spread their runs out.
+pub fn spawn(state: &ApiState) {
+	for task in TASKS {
+		let state = state.clone();
+		tokio::spawn(async move {
+			loop {
+				run(&state, task).await;
+
+				let jitter = rand::random_range(0..=task.interval.as_millis() as u64 / 10);
+				select! {
+					_ = state.shutdown.cancelled() => break,
+					_ = sleep(task.interval + Duration::from_millis(jitter)) => {}
+				}
+			}
+		});
+	}
+}
+
+async fn run(state: &ApiState, task: &Task) {
+	// Another instance may be running the same task, a run which has not finished within an interval is assumed to have
+	// been interrupted
+	let claimed = query!(
+		r#"INSERT INTO task_runs (name, running, started) VALUES ($1, true, LOCALTIMESTAMP)
+			ON CONFLICT (name) DO UPDATE SET running = true, started = LOCALTIMESTAMP
+			WHERE NOT task_runs.running OR task_runs.started < LOCALTIMESTAMP - make_interval(secs => $2)"#,
+		task.name,
+		task.interval.as_secs_f64()
+	)
+	.execute(&state.database)
+	.await;
+
+	match claimed {
+		Ok(result) if result.rows_affected() == 0 => {
+			debug!("Skipping task {}, it is already running", task.name);
+			return;
+		}
+		Err(error) => {
+			error!("Failed to start task {}: {error}", task.name);
+			return;
+		}
+		Ok(_) => {}
+	}
+
+	let start = Instant::now();
+	let result = (task.run)(state).await;
+	let duration = start.elapsed();
+
+	let error = result.err().map(|error| error.to_string());
+	if let Some(error) = &error {
+		error!("Error while running task {}: {error}", task.name);
+	}
+
+	let _ = query!(
+		r#"UPDATE task_runs SET running = false, finished = LOCALTIMESTAMP, duration_ms = $2, error = $3
+			WHERE name = $1"#,
+		task.name,
+		duration.as_millis() as i64,
+		error
+	)
+	.execute(&state.database)
+	.await;
+}
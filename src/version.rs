@@ -0,0 +1,76 @@
+use crate::{ApiState, config::Config, endpoints, endpoints::ApiDoc};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::{Router, middleware::map_response, response::Response};
+use chrono::NaiveDate;
+use utoipa::OpenApi;
+use utoipa_axum::router::OpenApiRouter;
+
+pub struct ApiVersion {
+	/// Path segment the version is mounted under
+	pub name: &'static str,
+	pub routes: fn(&Config) -> OpenApiRouter<ApiState>,
+	/// Sent in the `Deprecation` header once set, clients should start migrating to the successor version
+	pub deprecated: Option<NaiveDate>,
+	/// Sent in the `Sunset` header once set, the version may be removed after this date
+	pub sunset: Option<NaiveDate>,
+}
+
+/// Every mounted API version, oldest first. Once a new version is added the previous one should be marked as
+/// deprecated, its successor is the following entry.
+pub const VERSIONS: &[ApiVersion] = &[ApiVersion {
+	name: "v1",
+	routes: endpoints::v1,
+	deprecated: None,
+	sunset: None,
+}];
+
+/// Requests without a version prefix are served by this version, as older AxolotlClient builds rely on a reverse proxy
+/// stripping the prefix.
+const UNVERSIONED: &str = "v1";
+
+impl ApiVersion {
+	fn headers(&self, successor: Option<&ApiVersion>) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		if let Some(deprecated) = self.deprecated {
+			let timestamp = deprecated.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+			headers.insert("Deprecation", HeaderValue::from_str(&format!("@{timestamp}")).unwrap());
+		}
+		if let Some(sunset) = self.sunset {
+			let date = sunset.format("%a, %d %b %Y 00:00:00 GMT").to_string();
+			headers.insert("Sunset", HeaderValue::from_str(&date).unwrap());
+		}
+		if let Some(successor) = successor.filter(|_| self.deprecated.is_some()) {
+			let link = format!("</{}/>; rel=\"successor-version\"", successor.name);
+			headers.insert("Link", HeaderValue::from_str(&link).unwrap());
+		}
+		headers
+	}
+
+	fn router(&self, config: &Config, successor: Option<&ApiVersion>) -> OpenApiRouter<ApiState> {
+		let headers = self.headers(successor);
+		(self.routes)(config).layer(map_response(move |mut response: Response| {
+			let headers = headers.clone();
+			async move {
+				response.headers_mut().extend(headers);
+				response
+			}
+		}))
+	}
+}
+
+/// Mounts every version under its own prefix, they all share the same [`ApiState`].
+pub fn router(config: &Config) -> (Router<ApiState>, utoipa::openapi::OpenApi) {
+	let mut router = OpenApiRouter::with_openapi(ApiDoc::openapi());
+	let mut unversioned = Router::new();
+
+	for (index, version) in VERSIONS.iter().enumerate() {
+		let successor = VERSIONS.get(index + 1);
+		router = router.nest(&format!("/{}", version.name), version.router(config, successor));
+		if version.name == UNVERSIONED {
+			unversioned = version.router(config, successor).into();
+		}
+	}
+
+	let (router, openapi) = router.split_for_parts();
+	(router.merge(unversioned), openapi)
+}